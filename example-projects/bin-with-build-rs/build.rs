@@ -14,5 +14,9 @@ fn main() {
         }
         "#
     ).unwrap();
+    // Listed in `read_env_vars_from_build_script`, so it reaches `main.rs`
+    println!("cargo::rustc-env=MY_VAR=set by build.rs");
+    // Not listed, so buck2 drops it
+    println!("cargo::rustc-env=OTHER_VAR=unused");
     println!("cargo::rerun-if-changed=build.rs");
-}
\ No newline at end of file
+}
@@ -1,5 +1,6 @@
 include!(concat!(env!("OUT_DIR"), "/hello.rs"));
 
 fn main() {
-    println!("{}", message());
+    assert_eq!(env!("MY_VAR"), "set by build.rs");
+    println!("{} MY_VAR is {:?}", message(), env!("MY_VAR"));
 }
@@ -0,0 +1,7 @@
+// build.rs
+
+fn main() {
+    // Reaches the build scripts of dependents as `DEP_ANSWER_VALUE`
+    println!("cargo::metadata=value=42");
+    println!("cargo::rerun-if-changed=build.rs");
+}
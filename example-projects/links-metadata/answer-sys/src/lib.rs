@@ -0,0 +1,3 @@
+pub fn question() -> &'static str {
+    "the answer"
+}
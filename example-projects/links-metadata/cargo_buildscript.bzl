@@ -0,0 +1,129 @@
+# @generated by cargo2buck2, do not edit
+
+"""Runs cargo build scripts for the rules cargo2buck2 generates.
+
+Like the prelude's `buildscript_run`, but it also exports what the build script printed for
+the rules depending on it, as sub-targets:
+
+- `[out_dir]`: the script's `OUT_DIR`
+- `[rustc_flags]`: `--cfg` flags from `cargo:rustc-cfg` and `-l`/`-L` flags from
+  `cargo:rustc-link-lib`/`cargo:rustc-link-search`/`cargo:rustc-flags`, for the package's other
+  targets
+- `[env_flags]`: `--env=NAME=VALUE` flags from `cargo:rustc-env` for the names listed in
+  `read_env_vars`, for the package's other targets
+- `[metadata]`: `DEP_<LINKS>_<KEY>=VALUE` lines from the `cargo:KEY=VALUE` metadata of a `links`
+  package, for the build scripts of the packages depending on it
+"""
+
+load("@prelude//decls/toolchains_common.bzl", "toolchains_common")
+load("@prelude//rust:rust_toolchain.bzl", "RustToolchainInfo")
+
+def _flag(name, value):
+    return cmd_args(value, format = "--" + name + "={}")
+
+def _buildscript_run_impl(ctx: AnalysisContext) -> list[Provider]:
+    toolchain = ctx.attrs._rust_toolchain[RustToolchainInfo]
+    out_dir = ctx.actions.declare_output("out_dir", dir = True)
+    rustc_flags = ctx.actions.declare_output("rustc_flags")
+    env_flags = ctx.actions.declare_output("env_flags")
+    metadata = ctx.actions.declare_output("metadata")
+
+    if ctx.attrs.manifest_dir != None:
+        manifest_dir = ctx.attrs.manifest_dir[DefaultInfo].default_outputs[0]
+    else:
+        manifest_dir = ctx.actions.symlinked_dir("manifest_dir", ctx.attrs.srcs)
+
+    cmd = [
+        ctx.attrs._runner[RunInfo],
+        _flag("buildscript", ctx.attrs.buildscript[RunInfo]),
+        _flag("rustc", toolchain.compiler),
+        _flag("manifest-dir", manifest_dir),
+        _flag("out-dir", out_dir.as_output()),
+        _flag("rustc-flags", rustc_flags.as_output()),
+        _flag("env-flags", env_flags.as_output()),
+        _flag("metadata", metadata.as_output()),
+    ]
+    if ctx.attrs.manifest_subdir:
+        cmd.append(_flag("manifest-subdir", ctx.attrs.manifest_subdir))
+    if toolchain.rustc_target_triple:
+        cmd.append(_flag("target", toolchain.rustc_target_triple))
+    for flag in toolchain.rustc_flags:
+        cmd.append(_flag("rustflag", flag))
+    for name, value in ctx.attrs.env.items():
+        cmd.append(_flag("env=" + name, value))
+    for pattern in ctx.attrs.inputs or []:
+        cmd.append(_flag("input", pattern))
+    for name in ctx.attrs.env_passthrough:
+        cmd.append(_flag("env-passthrough", name))
+    for name in ctx.attrs.read_env_vars:
+        cmd.append(_flag("read-env-var", name))
+    for dep_metadata in ctx.attrs.links_metadata:
+        cmd.append(_flag("links-metadata", dep_metadata))
+
+    ctx.actions.run(cmd_args(cmd), category = "buildscript", identifier = ctx.attrs.name)
+
+    return [DefaultInfo(
+        default_output = None,
+        sub_targets = {
+            "env_flags": [DefaultInfo(default_output = env_flags)],
+            "metadata": [DefaultInfo(default_output = metadata)],
+            "out_dir": [DefaultInfo(default_output = out_dir)],
+            "rustc_flags": [DefaultInfo(default_output = rustc_flags)],
+        },
+    )]
+
+_buildscript_run = rule(
+    impl = _buildscript_run_impl,
+    attrs = {
+        "buildscript": attrs.exec_dep(providers = [RunInfo]),
+        "env": attrs.dict(key = attrs.string(), value = attrs.arg(), default = {}),
+        "env_passthrough": attrs.list(attrs.string(), default = []),
+        "features": attrs.list(attrs.string(), default = []),
+        "inputs": attrs.option(attrs.list(attrs.string()), default = None),
+        "links_metadata": attrs.list(attrs.source(), default = []),
+        "manifest_dir": attrs.option(attrs.dep(), default = None),
+        "manifest_subdir": attrs.option(attrs.string(), default = None),
+        "package_name": attrs.string(),
+        "read_env_vars": attrs.list(attrs.string(), default = []),
+        "srcs": attrs.dict(key = attrs.string(), value = attrs.source(), default = {}),
+        "version": attrs.string(),
+        "_runner": attrs.default_only(attrs.exec_dep(providers = [RunInfo], default = "//example-projects/links-metadata:cargo_buildscript_run")),
+        "_rust_toolchain": toolchains_common.rust(),
+    },
+)
+
+def buildscript_run(
+        name,
+        buildscript_rule,
+        package_name,
+        version,
+        local_manifest_dir = ".",
+        manifest_dir = None,
+        inputs = None,
+        **kwargs):
+    """Run the build script `buildscript_rule` of a package.
+
+    The build script sees the package's sources as its `CARGO_MANIFEST_DIR`: the output of the
+    rule `manifest_dir` (in `manifest_subdir`) for fetched crates, otherwise the files under
+    `local_manifest_dir`, relative to the calling `BUCK` file. When `inputs` lists glob patterns
+    of the files the build script reads, it only sees those and `Cargo.toml`, and local sources
+    only rerun it when they change.
+    """
+    srcs = {}
+    if manifest_dir == None:
+        prefix = "" if local_manifest_dir == "." else local_manifest_dir + "/"
+        patterns = inputs + ["Cargo.toml"] if inputs != None else ["**"]
+        matched = glob([prefix + pattern for pattern in patterns], exclude = [prefix + "target/**"])
+        for path in matched:
+            srcs[path[len(prefix):]] = path
+
+    _buildscript_run(
+        name = name,
+        buildscript = buildscript_rule,
+        package_name = package_name,
+        version = version,
+        manifest_dir = manifest_dir,
+        inputs = inputs,
+        srcs = srcs,
+        **kwargs
+    )
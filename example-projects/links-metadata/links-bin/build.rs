@@ -0,0 +1,16 @@
+// build.rs
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+fn main() {
+    let answer = env::var("DEP_ANSWER_VALUE").expect("`answer-sys` metadata is missing");
+    let out_dir = env::var("OUT_DIR").unwrap();
+    fs::write(
+        Path::new(&out_dir).join("answer.rs"),
+        format!("pub const ANSWER: u32 = {answer};\n"),
+    )
+    .unwrap();
+    println!("cargo::rerun-if-env-changed=DEP_ANSWER_VALUE");
+}
@@ -0,0 +1,6 @@
+include!(concat!(env!("OUT_DIR"), "/answer.rs"));
+
+fn main() {
+    assert_eq!(ANSWER, 42);
+    println!("{} is {ANSWER}", answer_sys::question());
+}
@@ -0,0 +1,24 @@
+// build.rs
+
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+fn run(command: &mut Command) {
+    let status = command.status().unwrap();
+    assert!(status.success(), "{command:?} failed");
+}
+
+fn main() {
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let object = Path::new(&out_dir).join("answer.o");
+    let archive = Path::new(&out_dir).join("libanswer.a");
+    run(Command::new("cc")
+        .args(["-c", "-fPIC", "c/answer.c", "-o"])
+        .arg(&object));
+    run(Command::new("ar").arg("rcs").arg(&archive).arg(&object));
+    // Both reach `main.rs` as `-l`/`-L` flags
+    println!("cargo::rustc-link-search=native={out_dir}");
+    println!("cargo::rustc-link-lib=static=answer");
+    println!("cargo::rerun-if-changed=c/answer.c");
+}
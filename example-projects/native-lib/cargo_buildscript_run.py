@@ -0,0 +1,251 @@
+# @generated by cargo2buck2, do not edit
+
+"""Runs a cargo build script and turns what it prints into files for buck2 rules.
+
+Sets the env vars cargo gives build scripts that depend on the machine running the build,
+runs the script in its package's directory, with only the files given with `--input` when there
+are any, then writes:
+
+- `--rustc-flags`: `--cfg`/`--check-cfg` flags and the `-l`/`-L` flags of the native libraries
+  to link, one per line
+- `--env-flags`: `--env=NAME=VALUE` flags from `cargo:rustc-env`, one per line, only for the
+  names given with `--read-env-var`
+- `--metadata`: `DEP_<LINKS>_<KEY>=VALUE` lines from the script's metadata, when the package
+  has `links`
+"""
+
+import argparse
+import glob
+import os
+import shutil
+import subprocess
+import sys
+import tempfile
+
+# Passed through from the build's environment to every build script, they need them to run
+# anything
+SYSTEM_ENV = ["PATH", "SYSTEMROOT", "TEMP", "TMP", "TMPDIR"]
+
+# Instructions with a meaning of their own, the old `cargo:KEY=VALUE` syntax is metadata for
+# every other key
+KNOWN_INSTRUCTIONS = {
+    "rerun-if-changed",
+    "rerun-if-env-changed",
+    "rustc-link-arg",
+    "rustc-link-arg-cdylib",
+    "rustc-cdylib-link-arg",
+    "rustc-link-arg-bin",
+    "rustc-link-arg-bins",
+    "rustc-link-arg-tests",
+    "rustc-link-arg-examples",
+    "rustc-link-arg-benches",
+    "rustc-link-lib",
+    "rustc-link-search",
+    "rustc-flags",
+    "rustc-cfg",
+    "rustc-check-cfg",
+    "rustc-env",
+    "warning",
+    "error",
+    "metadata",
+}
+
+
+def parse_args():
+    parser = argparse.ArgumentParser()
+    parser.add_argument("--buildscript", required=True)
+    parser.add_argument("--rustc", required=True)
+    parser.add_argument("--manifest-dir", required=True)
+    parser.add_argument("--manifest-subdir")
+    parser.add_argument("--out-dir", required=True)
+    parser.add_argument("--rustc-flags", required=True)
+    parser.add_argument("--env-flags", required=True)
+    parser.add_argument("--metadata", required=True)
+    parser.add_argument("--target")
+    parser.add_argument("--rustflag", action="append", default=[])
+    parser.add_argument("--env", action="append", default=[])
+    parser.add_argument("--input", action="append", default=[])
+    parser.add_argument("--env-passthrough", action="append", default=[])
+    parser.add_argument("--read-env-var", action="append", default=[])
+    parser.add_argument("--links-metadata", action="append", default=[])
+    return parser.parse_args()
+
+
+def rustc_output(rustc, *args):
+    return subprocess.run(
+        [rustc, *args], check=True, capture_output=True, text=True
+    ).stdout
+
+
+def target_env(rustc, target, rustflags):
+    """`HOST`, `TARGET`, `RUSTC`, `CARGO_ENCODED_RUSTFLAGS`, `NUM_JOBS` and the `CARGO_CFG_*`
+    vars"""
+    host = next(
+        line.split(":", 1)[1].strip()
+        for line in rustc_output(rustc, "-vV").splitlines()
+        if line.startswith("host:")
+    )
+    target = target or host
+    env = {
+        "HOST": host,
+        "TARGET": target,
+        "RUSTC": rustc,
+        "CARGO_ENCODED_RUSTFLAGS": "\x1f".join(rustflags),
+        "NUM_JOBS": str(os.cpu_count() or 1),
+    }
+    cfgs = {}
+    for line in rustc_output(rustc, "--print=cfg", "--target=" + target).splitlines():
+        key, _, value = line.partition("=")
+        values = cfgs.setdefault(key, [])
+        if value:
+            values.append(value.strip('"'))
+    # rustc is queried without profile flags so this one is always there, cargo leaves it out
+    cfgs.pop("debug_assertions", None)
+    for key, values in cfgs.items():
+        env["CARGO_CFG_" + key.upper().replace("-", "_")] = ",".join(values)
+    return env
+
+
+def read_links_metadata(paths):
+    env = {}
+    for path in paths:
+        with open(path, encoding="utf-8") as f:
+            for line in f.read().splitlines():
+                name, sep, value = line.partition("=")
+                if sep:
+                    env[name] = value
+    return env
+
+
+def copy_inputs(manifest_dir, patterns, dest):
+    """Copy the files of `manifest_dir` matching `patterns` (and `Cargo.toml`) to `dest`"""
+    for pattern in patterns + ["Cargo.toml"]:
+        for path in glob.glob(os.path.join(manifest_dir, pattern), recursive=True):
+            if not os.path.isfile(path):
+                continue
+            copy = os.path.join(dest, os.path.relpath(path, manifest_dir))
+            os.makedirs(os.path.dirname(copy), exist_ok=True)
+            shutil.copyfile(path, copy)
+
+
+def link_flags(flags):
+    """The `-l`/`-L` flags of `cargo:rustc-flags`, the only ones cargo accepts there"""
+    tokens = iter(flags.split())
+    for token in tokens:
+        if token in ("-l", "-L"):
+            value = next(tokens, None)
+            if value is None:
+                sys.exit("`cargo:rustc-flags={}` is missing a value".format(flags))
+            yield token + value
+        elif token.startswith(("-l", "-L")):
+            yield token
+        else:
+            sys.exit(
+                "only `-l` and `-L` are allowed in `cargo:rustc-flags`, got `{}`".format(token)
+            )
+
+
+def instructions(stdout):
+    """`(key, value)` of every `cargo:`/`cargo::` line, with the metadata of `cargo::metadata`
+    as `("metadata", "KEY=VALUE")`"""
+    for line in stdout.splitlines():
+        if line.startswith("cargo::"):
+            key, sep, value = line[len("cargo::") :].partition("=")
+        elif line.startswith("cargo:"):
+            key, sep, value = line[len("cargo:") :].partition("=")
+            if sep and key not in KNOWN_INSTRUCTIONS:
+                key, value = "metadata", key + "=" + value
+        else:
+            continue
+        if sep:
+            yield key, value
+
+
+def main():
+    args = parse_args()
+    if not args.input:
+        run(args, None)
+        return
+    # Only the declared inputs are visible, so the script can't depend on anything else
+    with tempfile.TemporaryDirectory() as inputs_dir:
+        run(args, inputs_dir)
+
+
+def run(args, inputs_dir):
+    rustc = shutil.which(args.rustc) or os.path.abspath(args.rustc)
+    manifest_dir = os.path.abspath(args.manifest_dir)
+    if args.manifest_subdir:
+        manifest_dir = os.path.join(manifest_dir, args.manifest_subdir)
+    if inputs_dir is not None:
+        copy_inputs(manifest_dir, args.input, inputs_dir)
+        manifest_dir = inputs_dir
+    out_dir = os.path.abspath(args.out_dir)
+    os.makedirs(out_dir, exist_ok=True)
+
+    env = {
+        name: os.environ[name]
+        for name in SYSTEM_ENV + args.env_passthrough
+        if name in os.environ
+    }
+    env.update(target_env(rustc, args.target, args.rustflag))
+    env.update(read_links_metadata(args.links_metadata))
+    for pair in args.env:
+        name, _, value = pair.partition("=")
+        env[name] = value
+    # `BUCK` files can only give paths relative to the project root, the script runs elsewhere
+    env["CARGO_MANIFEST_DIR"] = manifest_dir
+    env["CARGO_MANIFEST_PATH"] = os.path.join(manifest_dir, "Cargo.toml")
+    env["OUT_DIR"] = out_dir
+
+    result = subprocess.run(
+        [os.path.abspath(args.buildscript)],
+        cwd=manifest_dir,
+        env=env,
+        capture_output=True,
+        text=True,
+    )
+    sys.stderr.write(result.stderr)
+    if result.returncode != 0:
+        sys.stderr.write(result.stdout)
+        sys.exit("build script `{}` failed".format(args.buildscript))
+
+    rustc_flags = []
+    env_flags = []
+    metadata = []
+    links = env.get("CARGO_MANIFEST_LINKS")
+    for key, value in instructions(result.stdout):
+        if key == "rustc-cfg":
+            rustc_flags.append("--cfg=" + value)
+        elif key == "rustc-check-cfg":
+            rustc_flags.append("--check-cfg=" + value)
+        elif key == "rustc-link-lib":
+            rustc_flags.append("-l" + value)
+        elif key == "rustc-link-search":
+            rustc_flags.append("-L" + value)
+        elif key == "rustc-flags":
+            rustc_flags.extend(link_flags(value))
+        elif key.startswith("rustc-link-arg") or key == "rustc-cdylib-link-arg":
+            sys.stderr.write("warning: `cargo:{}` is not supported, ignoring it\n".format(key))
+        elif key == "rustc-env":
+            if value.partition("=")[0] in args.read_env_var:
+                env_flags.append("--env=" + value)
+        elif key == "metadata" and links:
+            name, sep, value = value.partition("=")
+            if sep:
+                name = name.upper().replace("-", "_")
+                links_name = links.upper().replace("-", "_")
+                metadata.append("DEP_{}_{}={}".format(links_name, name, value))
+        elif key == "warning":
+            sys.stderr.write("warning: {}\n".format(value))
+
+    for path, lines in [
+        (args.rustc_flags, rustc_flags),
+        (args.env_flags, env_flags),
+        (args.metadata, metadata),
+    ]:
+        with open(path, "w", encoding="utf-8") as f:
+            f.write("".join(line + "\n" for line in lines))
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,9 @@
+unsafe extern "C" {
+    fn answer() -> i32;
+}
+
+fn main() {
+    let answer = unsafe { answer() };
+    assert_eq!(answer, 42);
+    println!("The answer from C is {answer}");
+}
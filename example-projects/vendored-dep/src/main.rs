@@ -0,0 +1,5 @@
+use heck::ToKebabCase as _;
+
+fn main() {
+    println!("{}", "HELLO_WORLD".to_string().to_kebab_case());
+}
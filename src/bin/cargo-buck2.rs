@@ -0,0 +1,37 @@
+use cargo::{
+    CargoResult, GlobalContext, util::command_prelude::root_manifest, util::errors::CliError,
+};
+use cargo2buck2::{
+    buckify::buckify_workspaces,
+    cli::{Buck2Command, CargoBuck2, CargoCli, GenerateArgs},
+    output,
+};
+use clap::Parser as _;
+
+fn main() {
+    let CargoCli::Buck2(cli) = CargoCli::parse();
+    let mut gctx = match GlobalContext::default() {
+        Ok(gctx) => gctx,
+        Err(e) => {
+            let mut shell = cargo::core::Shell::new();
+            cargo::exit_with_error(CliError::new(e, 1), &mut shell)
+        }
+    };
+    if let Err(e) = run(cli, &mut gctx) {
+        cargo::exit_with_error(CliError::new(e, 101), &mut gctx.shell())
+    }
+}
+
+fn run(cli: CargoBuck2, gctx: &mut GlobalContext) -> CargoResult<()> {
+    cli.global.configure(gctx)?;
+
+    match cli.command {
+        Buck2Command::Generate(args) => generate(args, gctx),
+    }
+}
+
+fn generate(args: GenerateArgs, gctx: &GlobalContext) -> CargoResult<()> {
+    let manifest_path = root_manifest(args.manifest_path.as_deref(), gctx)?.canonicalize()?;
+    let buckified = buckify_workspaces(&[manifest_path], &args.common, gctx)?;
+    output::write_or_check(buckified, args.common.check, gctx)
+}
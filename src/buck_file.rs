@@ -1,11 +1,15 @@
 use std::collections::{BTreeMap, BTreeSet};
 
 use cargo::core::PackageId;
-use serde::Serialize;
+use serde::{Serialize, Serializer};
+
+use crate::{buckify::feature_env, platform::Platform};
 
 pub struct BuckFile {
     pkg_id_to_rules: BTreeMap<PackageId, BTreeSet<InternalRule>>,
-    add_buildscript_run_import: bool,
+    loads: BTreeSet<Load>,
+    /// Rules not belonging to any package
+    shared_rules: BTreeSet<InternalRule>,
 }
 
 pub trait StarlarkRule: Serialize + PartialEq + Eq + PartialOrd + Ord {
@@ -17,10 +21,19 @@ impl BuckFile {
     pub fn new() -> Self {
         Self {
             pkg_id_to_rules: BTreeMap::new(),
-            add_buildscript_run_import: false,
+            loads: BTreeSet::new(),
+            shared_rules: BTreeSet::new(),
         }
     }
 
+    pub fn add_load(&mut self, load: Load) {
+        self.loads.insert(load);
+    }
+
+    pub fn add_shared_rule(&mut self, rule: impl StarlarkRule) {
+        self.shared_rules.insert(rule.into_internal_rule());
+    }
+
     pub fn add_rule(&mut self, pkg_id: &PackageId, rule: impl StarlarkRule) {
         let pkg_set = match self.pkg_id_to_rules.get_mut(pkg_id) {
             Some(s) => s,
@@ -29,25 +42,39 @@ impl BuckFile {
                 self.pkg_id_to_rules.get_mut(pkg_id).unwrap()
             }
         };
-        let internal_rule = rule.into_internal_rule();
-        if matches!(internal_rule, InternalRule::BuildScriptRun(_)) {
-            self.add_buildscript_run_import = true;
+        pkg_set.insert(rule.into_internal_rule());
+    }
+
+    /// Add every rule of `other` to this file.
+    ///
+    /// The same third-party crate can be buckified from several workspaces with different
+    /// features enabled, rules sharing a name are merged the way cargo unifies features.
+    pub fn merge(&mut self, other: BuckFile) {
+        self.loads.extend(other.loads);
+        self.shared_rules.extend(other.shared_rules);
+        for (pkg_id, other_rules) in other.pkg_id_to_rules {
+            let rules = self.pkg_id_to_rules.entry(pkg_id).or_default();
+            let mut merged = std::mem::take(rules).into_iter().collect::<Vec<_>>();
+            for other_rule in other_rules {
+                let existing = merged
+                    .iter_mut()
+                    .find(|r| r.name().is_some() && r.name() == other_rule.name());
+                match existing {
+                    Some(existing) => existing.merge(other_rule),
+                    None => merged.push(other_rule),
+                }
+            }
+            *rules = merged.into_iter().collect();
         }
-        pkg_set.insert(internal_rule);
     }
 
     pub fn into_starlark_vec(self) -> Vec<u8> {
         let mut vec = vec![];
-        if self.add_buildscript_run_import {
-            vec.extend_from_slice(
-                Load(
-                    "@prelude//rust:cargo_buildscript.bzl".to_string(),
-                    "buildscript_run".to_string(),
-                )
-                .into_starlark()
-                .unwrap()
-                .as_bytes(),
-            );
+        for load in self.loads {
+            vec.extend_from_slice(load.into_starlark().unwrap().as_bytes());
+        }
+        for rule in self.shared_rules {
+            vec.extend_from_slice(rule.into_starlark().unwrap().as_bytes());
         }
 
         for (_, val) in self.pkg_id_to_rules {
@@ -66,9 +93,60 @@ pub enum InternalRule {
     Load(Load),
     RustLibrary(RustLibrary),
     HttpArchive(HttpArchive),
+    GitFetch(GitFetch),
     BuildScriptRun(BuildScriptRun),
+    RustTest(RustTest),
+    Alias(Alias),
+    PythonBootstrapBinary(PythonBootstrapBinary),
 }
 impl InternalRule {
+    fn name(&self) -> Option<&str> {
+        match self {
+            InternalRule::RustBinary(v) => Some(&v.name),
+            InternalRule::Glob(_) | InternalRule::Load(_) => None,
+            InternalRule::RustLibrary(v) => Some(&v.name),
+            InternalRule::HttpArchive(v) => Some(&v.name),
+            InternalRule::GitFetch(v) => Some(&v.name),
+            InternalRule::BuildScriptRun(v) => Some(&v.name),
+            InternalRule::RustTest(v) => Some(&v.name),
+            InternalRule::Alias(v) => Some(&v.name),
+            InternalRule::PythonBootstrapBinary(v) => Some(&v.name),
+        }
+    }
+
+    /// Merge two rules with the same name, taking the union of their features and deps
+    fn merge(&mut self, other: InternalRule) {
+        match (self, other) {
+            (InternalRule::RustBinary(a), InternalRule::RustBinary(b)) => {
+                a.features.merge(b.features);
+                a.deps.merge(b.deps);
+                a.named_deps.merge(b.named_deps);
+            }
+            (InternalRule::RustLibrary(a), InternalRule::RustLibrary(b)) => {
+                a.features.merge(b.features);
+                a.deps.merge(b.deps);
+                a.named_deps.merge(b.named_deps);
+            }
+            (InternalRule::RustTest(a), InternalRule::RustTest(b)) => {
+                a.features.merge(b.features);
+                a.deps.merge(b.deps);
+                a.named_deps.merge(b.named_deps);
+            }
+            (InternalRule::BuildScriptRun(a), InternalRule::BuildScriptRun(b)) => {
+                a.env.merge(b.env);
+                a.links_metadata.merge(b.links_metadata);
+                a.features.merge(b.features);
+                // The merged env holds the `CARGO_CFG_FEATURE` of whichever rule came last
+                for (platform, env) in &mut a.env.0 {
+                    let features = a.features.0.get(platform).map_or(&[][..], Vec::as_slice);
+                    feature_env(env, features);
+                }
+            }
+            // Everything else only depends on the package itself, so both copies are identical
+            _ => (),
+        }
+    }
+
     fn into_starlark(self) -> Result<String, serde_starlark::Error> {
         match self {
             InternalRule::RustBinary(v) => v.into_starlark(),
@@ -76,7 +154,11 @@ impl InternalRule {
             InternalRule::Load(v) => v.into_starlark(),
             InternalRule::RustLibrary(v) => v.into_starlark(),
             InternalRule::HttpArchive(v) => v.into_starlark(),
+            InternalRule::GitFetch(v) => v.into_starlark(),
             InternalRule::BuildScriptRun(v) => v.into_starlark(),
+            InternalRule::RustTest(v) => v.into_starlark(),
+            InternalRule::Alias(v) => v.into_starlark(),
+            InternalRule::PythonBootstrapBinary(v) => v.into_starlark(),
         }
     }
 }
@@ -89,18 +171,26 @@ pub struct RustBinary {
 
     pub srcs: Srcs,
     pub edition: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub named_deps: Option<BTreeMap<String, String>>,
+    #[serde(skip_serializing_if = "PerPlatform::is_empty")]
+    pub named_deps: PerPlatform<BTreeMap<String, String>>,
 
-    pub deps: Vec<String>,
+    pub deps: PerPlatform<Vec<String>>,
     pub crate_root: String,
     #[serde(rename = "crate")]
     pub crate_name: String,
-    pub features: Vec<String>,
+    pub features: PerPlatform<Vec<String>>,
     pub env: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rustc_flags: Option<Vec<String>>,
+    /// `@`-files of `--env=NAME=VALUE` lines added to the compile env, which is how
+    /// `cargo:rustc-env=` output of build scripts gets in
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub env_flags: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub labels: Vec<String>,
 }
 
-#[derive(Serialize, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Serialize, PartialEq, Eq, PartialOrd, Ord)]
 #[serde(rename = "glob")]
 pub struct Glob(pub BTreeSet<String>);
 
@@ -108,7 +198,7 @@ pub struct Glob(pub BTreeSet<String>);
 #[serde(rename = "load")]
 pub struct Load(pub String, pub String);
 
-#[derive(Serialize, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Serialize, PartialEq, Eq, PartialOrd, Ord)]
 #[serde(untagged)]
 pub enum Srcs {
     Glob(Glob),
@@ -120,26 +210,195 @@ pub enum Srcs {
 pub struct RustLibrary {
     pub name: String,
     pub visibility: Vec<String>,
-    pub srcs: Vec<String>,
+    pub srcs: Srcs,
     pub edition: String,
     pub crate_root: String,
     #[serde(rename = "crate")]
     pub crate_name: String,
     #[serde(skip_serializing_if = "is_false")]
     pub proc_macro: bool,
-    pub deps: Vec<String>,
+    /// `shared` for crates only built as a Rust `dylib`, so dependents link them dynamically
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preferred_linkage: Option<String>,
+    pub deps: PerPlatform<Vec<String>>,
+    #[serde(skip_serializing_if = "PerPlatform::is_empty")]
+    pub named_deps: PerPlatform<BTreeMap<String, String>>,
+    pub features: PerPlatform<Vec<String>>,
+    pub env: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rustc_flags: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub named_deps: Option<BTreeMap<String, String>>,
-    pub features: Vec<String>,
+    pub env_flags: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub labels: Vec<String>,
+}
+
+#[derive(Serialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename = "rust_test")]
+pub struct RustTest {
+    pub name: String,
+    pub visibility: Vec<String>,
+    pub srcs: Srcs,
+    pub edition: String,
+    pub crate_root: String,
+    #[serde(rename = "crate")]
+    pub crate_name: String,
+    pub deps: PerPlatform<Vec<String>>,
+    #[serde(skip_serializing_if = "PerPlatform::is_empty")]
+    pub named_deps: PerPlatform<BTreeMap<String, String>>,
+    pub features: PerPlatform<Vec<String>>,
     pub env: BTreeMap<String, String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub rustc_flags: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub env_flags: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub labels: Vec<String>,
+    /// `false` for `harness = false` tests, which are run as plain executables
+    #[serde(skip_serializing_if = "is_true")]
+    pub framework: bool,
+}
+
+#[derive(Serialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename = "alias")]
+pub struct Alias {
+    pub name: String,
+    pub actual: String,
+    pub visibility: Vec<String>,
+}
+
+#[derive(Serialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename = "python_bootstrap_binary")]
+pub struct PythonBootstrapBinary {
+    pub name: String,
+    pub main: String,
+    pub visibility: Vec<String>,
+}
+
+/// An attribute whose value can differ between the platforms the workspace was resolved for.
+///
+/// Serialized as a plain value when every platform agrees, otherwise as a `select()` on the
+/// `os` constraint, nesting a `select()` on the `cpu` constraint where needed.
+#[derive(Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PerPlatform<T>(pub BTreeMap<Platform, T>);
+
+/// Values [`PerPlatform`] knows how to combine when merging rules
+pub trait Union {
+    fn union(&mut self, other: Self);
+    fn is_empty(&self) -> bool;
+}
+
+impl Union for Vec<String> {
+    fn union(&mut self, other: Self) {
+        self.extend(other);
+        self.sort();
+        self.dedup();
+    }
+
+    fn is_empty(&self) -> bool {
+        self.is_empty()
+    }
+}
+
+impl Union for BTreeMap<String, String> {
+    fn union(&mut self, other: Self) {
+        self.extend(other);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.is_empty()
+    }
+}
+
+impl<T: Union> PerPlatform<T> {
+    fn merge(&mut self, other: PerPlatform<T>) {
+        for (platform, value) in other.0 {
+            match self.0.get_mut(&platform) {
+                Some(existing) => existing.union(value),
+                None => {
+                    self.0.insert(platform, value);
+                }
+            }
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.values().all(Union::is_empty)
+    }
+}
+
+impl PerPlatform<Vec<String>> {
+    /// Add `value` on every platform
+    pub fn add(&mut self, value: String) {
+        for values in self.0.values_mut() {
+            values.union(vec![value.clone()]);
+        }
+    }
+}
+
+impl<T: Serialize + PartialEq + Default> Serialize for PerPlatform<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0.is_empty() {
+            true => T::default().serialize(serializer),
+            false => selected(self.0.iter().collect()).serialize(serializer),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Selected<'a, T> {
+    Value(&'a T),
+    Select(Select<'a, T>),
+}
+
+#[derive(Serialize)]
+#[serde(rename = "select")]
+struct Select<'a, T>(BTreeMap<&'a str, Selected<'a, T>>);
+
+/// Collapse the values of a non-empty set of platforms into as few `select()`s as possible.
+///
+/// Values that differ are always selected on `os` first, so a `select()` never matches on a
+/// `cpu` alone for platforms whose `os` was never resolved.
+fn selected<'a, T: PartialEq>(values: Vec<(&'a Platform, &'a T)>) -> Selected<'a, T> {
+    let first = values[0].1;
+    if values.iter().all(|(_, value)| *value == first) {
+        return Selected::Value(first);
+    }
+    let oses = values
+        .iter()
+        .map(|(platform, _)| platform.os.as_str())
+        .collect::<BTreeSet<_>>();
+    Selected::Select(Select(
+        oses.into_iter()
+            .map(|os| {
+                let on_os = values
+                    .iter()
+                    .filter(|(platform, _)| platform.os == os)
+                    .collect::<Vec<_>>();
+                let first = on_os[0].1;
+                if on_os.iter().all(|(_, value)| *value == first) {
+                    return (os, Selected::Value(first));
+                }
+                // Platforms are unique, so on one os they only differ by cpu
+                let by_cpu = on_os
+                    .into_iter()
+                    .map(|(platform, value)| (platform.cpu.as_str(), Selected::Value(*value)))
+                    .collect();
+                (os, Selected::Select(Select(by_cpu)))
+            })
+            .collect(),
+    ))
 }
 
 fn is_false(b: &bool) -> bool {
     !b
 }
 
+fn is_true(b: &bool) -> bool {
+    *b
+}
+
 #[derive(Serialize, PartialEq, Eq, PartialOrd, Ord)]
 #[serde(rename = "http_archive")]
 pub struct HttpArchive {
@@ -150,15 +409,52 @@ pub struct HttpArchive {
     pub visibility: Vec<String>,
 }
 
+#[derive(Serialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename = "git_fetch")]
+pub struct GitFetch {
+    pub name: String,
+    pub repo: String,
+    pub rev: String,
+    pub visibility: Vec<String>,
+}
+
 #[derive(Serialize, PartialEq, Eq, PartialOrd, Ord)]
 #[serde(rename = "buildscript_run")]
 pub struct BuildScriptRun {
     pub name: String,
+    /// Public so dependents in other `BUCK` files can read its `[metadata]`
+    pub visibility: Vec<String>,
     pub package_name: String,
     pub buildscript_rule: String,
-    pub env: BTreeMap<String, String>,
-    pub features: Vec<String>,
+    pub env: PerPlatform<BTreeMap<String, String>>,
+    /// `[metadata]` of the `buildscript_run` rules of direct `links` dependencies, their
+    /// `DEP_<LINKS>_<KEY>=VALUE` lines are added to the env the build script runs in
+    #[serde(skip_serializing_if = "PerPlatform::is_empty")]
+    pub links_metadata: PerPlatform<Vec<String>>,
+    pub features: PerPlatform<Vec<String>>,
     pub version: String,
+    /// Directory of the package's sources relative to the `BUCK` file, when they are in the
+    /// repository
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub local_manifest_dir: Option<String>,
+    /// Rule downloading the package's sources, when they are not
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manifest_dir: Option<String>,
+    /// Directory of the package inside the output of `manifest_dir`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manifest_subdir: Option<String>,
+    /// Glob patterns of the files the build script reads relative to the package root, when
+    /// declared it only sees these and its run only depends on them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inputs: Option<BTreeSet<String>>,
+    /// Variables set with `cargo:rustc-env=` that reach the package's other targets, the others
+    /// are dropped
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    pub read_env_vars: BTreeSet<String>,
+    /// Variables of the build's environment the build script reads, nothing else but `PATH` and
+    /// the temp dir is passed through
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    pub env_passthrough: BTreeSet<String>,
 }
 
 macro_rules! impl_starlark_rule {
@@ -178,4 +474,143 @@ impl_starlark_rule!(Glob);
 impl_starlark_rule!(Load);
 impl_starlark_rule!(RustLibrary);
 impl_starlark_rule!(HttpArchive);
+impl_starlark_rule!(GitFetch);
 impl_starlark_rule!(BuildScriptRun);
+impl_starlark_rule!(RustTest);
+impl_starlark_rule!(Alias);
+impl_starlark_rule!(PythonBootstrapBinary);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn platform(os: &str, cpu: &str) -> Platform {
+        Platform {
+            os: format!("config//os:{os}"),
+            cpu: format!("config//cpu:{cpu}"),
+        }
+    }
+
+    fn starlark(values: &[(Platform, Vec<&str>)]) -> String {
+        let per_platform = PerPlatform(
+            values
+                .iter()
+                .map(|(platform, value)| {
+                    let value = value.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+                    (platform.clone(), value)
+                })
+                .collect(),
+        );
+        serde_starlark::to_string(&per_platform).unwrap()
+    }
+
+    #[test]
+    fn same_value_everywhere_is_not_selected() {
+        let values = [
+            (platform("linux", "x86_64"), vec!["a"]),
+            (platform("macos", "arm64"), vec!["a"]),
+        ];
+        assert_eq!(starlark(&values), "[\"a\"]\n");
+    }
+
+    #[test]
+    fn values_differing_by_os_are_selected_on_os() {
+        let values = [
+            (platform("linux", "x86_64"), vec!["a"]),
+            (platform("macos", "arm64"), vec!["b"]),
+        ];
+        assert_eq!(
+            starlark(&values),
+            r#"select({
+    "config//os:linux": ["a"],
+    "config//os:macos": ["b"],
+})
+"#
+        );
+    }
+
+    #[test]
+    fn values_differing_by_cpu_are_still_selected_on_os_first() {
+        let values = [
+            (platform("linux", "arm64"), vec!["a"]),
+            (platform("linux", "x86_64"), vec!["b"]),
+        ];
+        assert_eq!(
+            starlark(&values),
+            r#"select({
+    "config//os:linux": select({
+        "config//cpu:arm64": ["a"],
+        "config//cpu:x86_64": ["b"],
+    }),
+})
+"#
+        );
+    }
+
+    #[test]
+    fn cpu_select_is_only_nested_where_needed() {
+        let values = [
+            (platform("linux", "arm64"), vec!["a"]),
+            (platform("linux", "x86_64"), vec!["b"]),
+            (platform("macos", "arm64"), vec!["c"]),
+            (platform("macos", "x86_64"), vec!["c"]),
+        ];
+        assert_eq!(
+            starlark(&values),
+            r#"select({
+    "config//os:linux": select({
+        "config//cpu:arm64": ["a"],
+        "config//cpu:x86_64": ["b"],
+    }),
+    "config//os:macos": ["c"],
+})
+"#
+        );
+    }
+
+    #[test]
+    fn no_platforms_is_the_default() {
+        assert_eq!(starlark(&[]), "[]\n");
+    }
+    fn on_linux<T>(value: T) -> PerPlatform<T> {
+        PerPlatform(BTreeMap::from([(platform("linux", "x86_64"), value)]))
+    }
+
+    fn build_script_run(features: &[&str]) -> InternalRule {
+        let features = features.iter().map(|f| f.to_string()).collect::<Vec<_>>();
+        let mut env = BTreeMap::new();
+        feature_env(&mut env, &features);
+        InternalRule::BuildScriptRun(BuildScriptRun {
+            name: "foo-1.0.0-build-script-run".to_string(),
+            visibility: Vec::new(),
+            package_name: "foo".to_string(),
+            buildscript_rule: ":foo-1.0.0-build-script-build".to_string(),
+            env: on_linux(env),
+            links_metadata: PerPlatform::default(),
+            features: on_linux(features),
+            version: "1.0.0".to_string(),
+            local_manifest_dir: None,
+            manifest_dir: None,
+            manifest_subdir: None,
+            inputs: None,
+            read_env_vars: BTreeSet::new(),
+            env_passthrough: BTreeSet::new(),
+        })
+    }
+
+    #[test]
+    fn merged_build_script_runs_see_every_feature() {
+        let mut merged = build_script_run(&["a", "c"]);
+        merged.merge(build_script_run(&["b"]));
+        let InternalRule::BuildScriptRun(merged) = merged else {
+            unreachable!()
+        };
+        let env = &merged.env.0[&platform("linux", "x86_64")];
+        assert_eq!(env["CARGO_CFG_FEATURE"], "a,b,c");
+        assert!(
+            ["A", "B", "C"]
+                .iter()
+                .all(|f| env.contains_key(&format!("CARGO_FEATURE_{f}")))
+        );
+    }
+}
@@ -0,0 +1,1218 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, btree_map::Entry},
+    path::{Path, PathBuf},
+};
+
+use cargo::{
+    CargoResult, GlobalContext,
+    core::{
+        Package, PackageId, Target, TargetKind, Workspace,
+        compiler::{CompileKind, CompileTarget, CrateType, RustcTargetData},
+        dependency::DepKind,
+        profiles::{Profile, ProfileRoot, Profiles},
+        resolver::{
+            CliFeatures,
+            features::{FeaturesFor, ResolvedFeatures},
+        },
+    },
+    ops::resolve_ws_with_opts,
+    util::interning::InternedString,
+};
+use cargo_platform::Cfg;
+use cargo_util_schemas::manifest::TomlDebugInfo;
+
+use crate::{
+    buck_file::{Alias, BuckFile, BuildScriptRun, PerPlatform, RustBinary, RustLibrary, RustTest},
+    buildscript_run,
+    cli::{CommonArgs, GenerationArgs, ResolveArgs},
+    custom_metadata::{CrateOverride, CustomMetadata, WorkspaceMetadata},
+    error::{Cargo2Buck2Error, Result, Unsupported},
+    layout::{
+        self, Layout, bin_rule_name, build_script_build_rule_name, build_script_run_rule_name,
+        lib_rule_name,
+    },
+    module_tree,
+    output::BuckFiles,
+    platform::Platform,
+    shared_dir::{RECORD_FILE, RecordedWorkspace, SharedDir},
+    sources::{RegistryUrls, SourceBackend, VENDOR_DIR},
+    vendor::{self, VendoredCrate},
+};
+
+/// Options shared by every workspace buckified in a single invocation
+pub struct BuckifyOptions {
+    /// Where to write the workspace root's `BUCK` file, `None` means the workspace root.
+    /// Member `BUCK` files are placed relative to it
+    pub out_dir: Option<PathBuf>,
+    /// Directory shared by every workspace for the rules of third-party crates, `None` means
+    /// each workspace keeps its own in the workspace root's `BUCK` file
+    pub third_party_dir: Option<PathBuf>,
+    /// Skip targets we can't buckify instead of failing
+    pub keep_going: bool,
+    /// Copy third-party sources into the repo instead of downloading them
+    pub vendor: bool,
+    /// Only checking the generated files, so a stale `Cargo.lock` is resolved without being
+    /// written back
+    pub check: bool,
+    pub cli_features: CliFeatures,
+    pub compile_kinds: Vec<CompileKind>,
+}
+
+impl BuckifyOptions {
+    pub fn from_args(
+        args: &ResolveArgs,
+        generation: &GenerationArgs,
+        out_dir: Option<PathBuf>,
+        check: bool,
+    ) -> CargoResult<Self> {
+        let cli_features = CliFeatures::from_command_line(
+            &args.features,
+            args.all_features,
+            !args.no_default_features,
+        )?;
+        let compile_kinds = match args.targets.is_empty() {
+            true => vec![CompileKind::Host],
+            false => args
+                .targets
+                .iter()
+                .map(|triple| CompileTarget::new(triple).map(CompileKind::Target))
+                .collect::<CargoResult<_>>()?,
+        };
+        Ok(Self {
+            out_dir,
+            third_party_dir: generation.third_party_dir.clone(),
+            keep_going: generation.keep_going,
+            vendor: generation.vendor,
+            check,
+            cli_features,
+            compile_kinds,
+        })
+    }
+}
+
+/// Everything generated for one or more workspaces
+#[derive(Default)]
+pub struct Buckified {
+    /// Generated `BUCK` files, keyed by the directory they belong in
+    buck_files: BTreeMap<PathBuf, BuckFile>,
+    /// Third-party crates to vendor, keyed by the directory they are copied into
+    vendored: BTreeMap<PathBuf, VendoredCrate>,
+    /// Other generated files, keyed by their path
+    files: BuckFiles,
+    /// Crates vendored by earlier runs that are no longer used
+    unused_vendored: BTreeSet<PathBuf>,
+    /// Root manifests of the workspaces buckified
+    root_manifests: BTreeSet<PathBuf>,
+}
+
+impl Buckified {
+    /// Add the files generated for another workspace, third-party rules both workspaces
+    /// generated are deduplicated
+    pub fn merge(&mut self, other: Buckified) {
+        for (dir, buck_file) in other.buck_files {
+            match self.buck_files.get_mut(&dir) {
+                Some(existing) => existing.merge(buck_file),
+                None => {
+                    self.buck_files.insert(dir, buck_file);
+                }
+            }
+        }
+        self.vendored.extend(other.vendored);
+        self.files.extend(other.files);
+        self.unused_vendored.extend(other.unused_vendored);
+        self.root_manifests.extend(other.root_manifests);
+    }
+
+    /// Drop everything generated outside of `dir`
+    fn retain_dir(&mut self, dir: &Path) {
+        self.buck_files.retain(|buck_dir, _| buck_dir == dir);
+        self.vendored.retain(|dest, _| dest.starts_with(dir));
+        self.files.retain(|path, _| path.starts_with(dir));
+        self.unused_vendored.retain(|path| path.starts_with(dir));
+    }
+
+    /// The contents of every `BUCK` file, the crates to vendor next to them and the vendored
+    /// crates to remove
+    pub(crate) fn into_output(
+        self,
+    ) -> (
+        BuckFiles,
+        BTreeMap<PathBuf, VendoredCrate>,
+        BTreeSet<PathBuf>,
+    ) {
+        let mut files = self
+            .buck_files
+            .into_iter()
+            .map(|(dir, buck_file)| (dir.join("BUCK"), buck_file.into_starlark_vec()))
+            .collect::<BuckFiles>();
+        files.extend(self.files);
+        (files, self.vendored, self.unused_vendored)
+    }
+}
+
+/// Generate the `BUCK` files for every workspace in `manifest_paths`, without writing them
+///
+/// When third-party crates go to a shared directory, the workspaces earlier runs put there are
+/// buckified again as they were back then, and only their share of the directory is kept. The
+/// directory's record of workspaces is updated with the ones given here.
+pub fn buckify_workspaces(
+    manifest_paths: &[PathBuf],
+    args: &CommonArgs,
+    gctx: &GlobalContext,
+) -> CargoResult<Buckified> {
+    let CommonArgs {
+        resolve,
+        generation,
+        out_dir,
+        check,
+    } = args;
+    let opts = BuckifyOptions::from_args(resolve, generation, out_dir.clone(), *check)?;
+    let mut buckified = Buckified::default();
+    for manifest_path in manifest_paths {
+        gctx.shell().status("Buckifying", manifest_path.display())?;
+        buckified.merge(buckify_workspace(manifest_path, gctx, &opts)?);
+    }
+    let Some(third_party_dir) = &opts.third_party_dir else {
+        return Ok(buckified);
+    };
+
+    let third_party_dir = layout::absolute(third_party_dir);
+    let mut shared = SharedDir::load(&third_party_dir)?;
+    // Workspaces are recorded by their root manifest, whichever member they were buckified from
+    let requested = buckified.root_manifests.clone();
+    for (manifest_path, recorded) in shared.workspaces(&third_party_dir) {
+        let Ok(manifest_path) = manifest_path.canonicalize() else {
+            gctx.shell().warn(format!(
+                "forgetting workspace `{}` sharing `{}`, it no longer exists",
+                manifest_path.display(),
+                third_party_dir.display()
+            ))?;
+            shared.remove(&third_party_dir, &manifest_path);
+            continue;
+        };
+        if buckified.root_manifests.contains(&manifest_path) {
+            continue;
+        }
+        gctx.shell().status("Buckifying", manifest_path.display())?;
+        let generation = GenerationArgs {
+            keep_going: recorded.keep_going,
+            third_party_dir: Some(third_party_dir.clone()),
+            vendor: generation.vendor,
+        };
+        let opts = BuckifyOptions::from_args(&recorded.resolve, &generation, None, *check)?;
+        let mut other = buckify_workspace(&manifest_path, gctx, &opts)?;
+        // Records of a member, from before they were keyed by the root manifest, move to the root
+        if !other.root_manifests.contains(&manifest_path) {
+            shared.remove(&third_party_dir, &manifest_path);
+            if other.root_manifests.is_subset(&buckified.root_manifests) {
+                continue;
+            }
+            for root_manifest in &other.root_manifests {
+                shared.insert(&third_party_dir, root_manifest, recorded.clone());
+            }
+        }
+        other.retain_dir(&third_party_dir);
+        buckified.merge(other);
+    }
+    if opts.vendor {
+        let vendored = buckified
+            .vendored
+            .keys()
+            .filter(|dest| dest.starts_with(&third_party_dir));
+        buckified.unused_vendored = shared.replace_vendored(&third_party_dir, vendored);
+    }
+    for root_manifest in &requested {
+        let recorded = RecordedWorkspace {
+            resolve: resolve.clone(),
+            keep_going: generation.keep_going,
+        };
+        shared.insert(&third_party_dir, root_manifest, recorded);
+    }
+    buckified
+        .files
+        .insert(third_party_dir.join(RECORD_FILE), shared.to_vec());
+    Ok(buckified)
+}
+
+/// Generate the `BUCK` files for the workspace `manifest_path` belongs to, without writing them
+///
+/// Every path package gets its own `BUCK` file next to its `Cargo.toml`, third-party crates
+/// share the one at the workspace root, or the one in [`BuckifyOptions::third_party_dir`].
+///
+/// Targets using constructs we can't express in buck2 yet are collected and reported together,
+/// unless [`BuckifyOptions::keep_going`] is set in which case they are skipped with a warning.
+pub fn buckify_workspace(
+    manifest_path: &Path,
+    gctx: &GlobalContext,
+    opts: &BuckifyOptions,
+) -> Result<Buckified> {
+    let mut buck_files = BTreeMap::new();
+    let mut files = BuckFiles::new();
+    let mut unsupported = Vec::new();
+    let ws = Workspace::new(manifest_path, gctx).map_err(|source| Cargo2Buck2Error::Workspace {
+        manifest_path: manifest_path.to_path_buf(),
+        source,
+    })?;
+    let members = ws
+        .members()
+        .map(|p| p.package_id())
+        .collect::<BTreeSet<_>>();
+    let specs = members
+        .iter()
+        .map(|pkg_id| pkg_id.to_spec())
+        .collect::<Vec<_>>();
+    let ws_metadata: WorkspaceMetadata = ws
+        .custom_metadata()
+        .and_then(|custom_meta| custom_meta.get("cargo2buck2"))
+        .map(|v| v.to_owned().try_into::<WorkspaceMetadata>())
+        .transpose()
+        .map_err(|e| Cargo2Buck2Error::InvalidWorkspaceMetadata {
+            manifest_path: ws.root_manifest().to_path_buf(),
+            source: e.into(),
+        })?
+        .unwrap_or_default();
+    let mut source_backend = match opts.vendor {
+        true => SourceBackend::Vendor(BTreeMap::new()),
+        false => SourceBackend::Fetch(RegistryUrls::new(gctx, ws_metadata.registries)),
+    };
+    let layout = Layout::new(
+        ws.root(),
+        opts.out_dir.as_deref(),
+        opts.third_party_dir.as_deref(),
+    );
+    let resolve_err = |source| Cargo2Buck2Error::Resolve {
+        manifest_path: manifest_path.to_path_buf(),
+        source,
+    };
+    let mut target_data = RustcTargetData::new(&ws, &opts.compile_kinds).map_err(resolve_err)?;
+    // Build scripts see the profile the package's library is built with, buck2 modes don't map
+    // onto cargo profiles so they get `dev`
+    let profile = Profiles::new(&ws, InternedString::new("dev"))
+        .map_err(resolve_err)?
+        .base_profile();
+
+    // Every platform is resolved on its own, so each gets the deps and features cargo would
+    // use when building for it
+    let mut packages: BTreeMap<PackageId, (Package, BTreeMap<Platform, PlatformPackage>)> =
+        BTreeMap::new();
+    let mut triples = BTreeMap::new();
+    let mut platform_envs = BTreeMap::new();
+    let mut outside = BTreeMap::new();
+    for &kind in &opts.compile_kinds {
+        let platform = Platform::new(&target_data, kind)?;
+        let triple = target_data.short_name(&kind).to_string();
+        if let Some(other) = triples.insert(platform.clone(), triple.clone()) {
+            return Err(Cargo2Buck2Error::UnsupportedPlatform {
+                target: triple,
+                reason: format!("it has the same buck2 os and cpu constraints as `{other}`"),
+            });
+        }
+        let mut env = profile_env(&profile);
+        // Values depending on the target are only known when it is selected on, otherwise
+        // `buildscript_run` sets them for the platform the build is for
+        if opts.compile_kinds.len() > 1 {
+            env.extend(target_env(&target_data, kind));
+        }
+        platform_envs.insert(platform.clone(), env);
+
+        let resolved = resolve_ws_with_opts(
+            &ws,
+            &mut target_data,
+            &[kind],
+            &opts.cli_features,
+            &specs,
+            cargo::core::resolver::HasDevUnits::Yes,
+            cargo::core::resolver::ForceAllTargets::No,
+            opts.check,
+        )
+        .map_err(resolve_err)?;
+        let resolved_features = resolved
+            .specs_and_features
+            .iter()
+            .map(|s| &s.resolved_features)
+            .collect::<Vec<_>>();
+
+        for pkg_id in resolved.targeted_resolve.iter() {
+            // Packages only used on other platforms have no features activated
+            let Some(features) = activated_features(&resolved_features, pkg_id) else {
+                continue;
+            };
+            // Path packages outside of the workspace only have a place with `--out-dir` when
+            // they are members, the others are skipped along with the deps on them
+            let pkg_dir = match layout.package_dir(pkg_id) {
+                Ok(pkg_dir) => pkg_dir,
+                Err(e @ Cargo2Buck2Error::OutsideWorkspace { .. }) => {
+                    outside.entry(pkg_id).or_insert(e);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            let per_platform = match packages.entry(pkg_id) {
+                Entry::Occupied(entry) => &mut entry.into_mut().1,
+                Entry::Vacant(entry) => {
+                    let pkg = resolved.pkg_set.get_one(pkg_id).map_err(|source| {
+                        Cargo2Buck2Error::Download {
+                            package: pkg_id,
+                            source,
+                        }
+                    })?;
+                    &mut entry.insert((pkg.clone(), BTreeMap::new())).1
+                }
+            };
+
+            let mut platform_pkg = PlatformPackage {
+                features,
+                ..Default::default()
+            };
+            for (dep_id, dep_set) in resolved.targeted_resolve.deps(pkg_id) {
+                let label = match layout.label(&pkg_dir, dep_id, &lib_rule_name(dep_id)) {
+                    Ok(label) => label,
+                    Err(Cargo2Buck2Error::OutsideWorkspace { .. }) => continue,
+                    Err(e) => return Err(e),
+                };
+                // The same package can be depended on as several kinds, e.g. both as a normal
+                // and as a build dependency
+                for dep in dep_set {
+                    let dep_kind = match dep.kind() {
+                        DepKind::Build => CompileKind::Host,
+                        DepKind::Normal | DepKind::Development => kind,
+                    };
+                    let activated = target_data.dep_platform_activated(dep, dep_kind)
+                        && (!dep.is_optional()
+                            || is_dep_activated(&resolved_features, pkg_id, dep.name_in_toml()));
+                    if !activated {
+                        continue;
+                    }
+                    match dep.kind() {
+                        DepKind::Normal => {
+                            platform_pkg.normal_dep_ids.insert(dep_id);
+                        }
+                        DepKind::Development => {
+                            platform_pkg.dev_dep_ids.insert(dep_id);
+                        }
+                        DepKind::Build => (),
+                    }
+                    match dep.explicit_name_in_toml() {
+                        Some(explicit_name_in_toml) => {
+                            platform_pkg
+                                .named_deps
+                                .entry(dep.kind())
+                                .or_default()
+                                .insert(explicit_name_in_toml.to_string(), label.clone());
+                        }
+                        None => {
+                            platform_pkg
+                                .deps
+                                .entry(dep.kind())
+                                .or_default()
+                                .insert(label.clone());
+                        }
+                    }
+                }
+            }
+            per_platform.insert(platform.clone(), platform_pkg);
+        }
+    }
+
+    unsupported.extend(outside.into_values());
+
+    let mut metadata_by_pkg = BTreeMap::new();
+    for (pkg, _) in packages.values() {
+        metadata_by_pkg.insert(
+            pkg.package_id(),
+            package_metadata(pkg, &ws_metadata.crates)?,
+        );
+    }
+    let is_sandboxed_proc_macro = |pkg_id: &PackageId| {
+        packages[pkg_id].0.proc_macro() && metadata_by_pkg[pkg_id].sandboxed_proc_macro
+    };
+
+    for (pkg, per_platform) in packages.values() {
+        let package_id = pkg.package_id();
+        let metadata = &metadata_by_pkg[&package_id];
+        // Informational labels marking targets whose proc macros are all sandboxed, computed from
+        // the deps each target is built with
+        let proc_macro_labels = |with_dev_deps: bool| {
+            let proc_macro_deps = per_platform
+                .values()
+                .flat_map(|platform_pkg| {
+                    let dev_dep_ids = with_dev_deps.then_some(&platform_pkg.dev_dep_ids);
+                    platform_pkg
+                        .normal_dep_ids
+                        .iter()
+                        .chain(dev_dep_ids.into_iter().flatten())
+                })
+                .filter(|dep_id| {
+                    packages
+                        .get(dep_id)
+                        .is_some_and(|(dep, _)| dep.proc_macro())
+                })
+                .collect::<BTreeSet<_>>();
+            match !proc_macro_deps.is_empty()
+                && proc_macro_deps.into_iter().all(is_sandboxed_proc_macro)
+            {
+                true => vec!["uses_sandboxed_proc_macros".to_string()],
+                false => Vec::new(),
+            }
+        };
+        let labels = proc_macro_labels(false);
+        let dev_labels = proc_macro_labels(true);
+        let pkg_dir = layout.package_dir(package_id)?;
+        let normal_deps = TargetDeps::of_kinds(per_platform, &[DepKind::Normal]);
+        let build_deps = TargetDeps::of_kinds(per_platform, &[DepKind::Build]);
+        let test_deps =
+            TargetDeps::of_kinds(per_platform, &[DepKind::Normal, DepKind::Development]);
+        let is_member = members.contains(&package_id);
+        let features = PerPlatform(
+            per_platform
+                .iter()
+                .map(|(platform, platform_pkg)| (platform.clone(), platform_pkg.features.clone()))
+                .collect(),
+        );
+        // `cargo:KEY=VALUE` lines of the build scripts of `links` dependencies reach this one's
+        // as `DEP_<LINKS>_<KEY>`
+        let mut links_metadata = PerPlatform(BTreeMap::new());
+        for (platform, platform_pkg) in per_platform {
+            let mut labels = Vec::new();
+            for &dep_id in &platform_pkg.normal_dep_ids {
+                let Some((dep, _)) = packages.get(&dep_id) else {
+                    continue;
+                };
+                if dep.manifest().links().is_some() && dep.has_custom_build() {
+                    let rule = build_script_run_rule_name(dep_id);
+                    labels.push(format!(
+                        "{}[metadata]",
+                        layout.label(&pkg_dir, dep_id, &rule)?
+                    ));
+                }
+            }
+            links_metadata.0.insert(platform.clone(), labels);
+        }
+        // Build scripts run through our own `buildscript_run`, which lives in the third-party
+        // directory
+        let buildscript_run_dir = match pkg.has_custom_build() {
+            true => {
+                let third_party_dir = layout.third_party_dir().to_path_buf();
+                let buck_dir = layout.buck_path(package_id, &third_party_dir)?;
+                files.extend(buildscript_run::files(&third_party_dir, &buck_dir));
+                buck_files
+                    .entry(third_party_dir)
+                    .or_insert_with(BuckFile::new)
+                    .add_shared_rule(buildscript_run::runner_rule());
+                Some(buck_dir)
+            }
+            false => None,
+        };
+        let buck_file = buck_files
+            .entry(pkg_dir.clone())
+            .or_insert_with(BuckFile::new);
+        // Failing to locate a package's sources is a real error, not a target to skip
+        let sources = source_backend.package_sources(pkg, buck_file, &pkg_dir)?;
+        // `cargo:rustc-cfg`, `cargo:rustc-link-lib`, ... of the build script apply to every other
+        // target of the package, `cargo:rustc-link-arg*` are dropped with a warning
+        let rustc_flags = match pkg.has_custom_build() {
+            true => Some(vec![format!(
+                "@$(location :{}[rustc_flags])",
+                build_script_run_rule_name(package_id)
+            )]),
+            false => None,
+        };
+        // Build scripts can set env vars for the package's other targets, only packages listing
+        // the ones they read get the script's env output
+        let env_flags =
+            match pkg.has_custom_build() && !metadata.read_env_vars_from_build_script.is_empty() {
+                true => Some(vec![format!(
+                    "@$(location :{}[env_flags])",
+                    build_script_run_rule_name(package_id)
+                )]),
+                false => None,
+            };
+        let manifest_dir = sources.manifest_dir(&layout.buck_path(package_id, &pkg_dir)?);
+        let pkg_env = package_env(pkg, &manifest_dir, is_member);
+        let links = pkg.manifest().links();
+        let build_script_env = PerPlatform(
+            per_platform
+                .iter()
+                .map(|(platform, platform_pkg)| {
+                    let mut env = pkg_env.clone();
+                    // cargo only sets it when compiling the package's own crates
+                    env.remove("CARGO_PRIMARY_PACKAGE");
+                    env.extend(platform_envs[platform].clone());
+                    build_script_env(&mut env, &platform_pkg.features, links);
+                    (platform.clone(), env)
+                })
+                .collect(),
+        );
+
+        // Targets we can generate rules for, with their `srcs` and `crate_root`
+        let mut targets = Vec::new();
+        for target in pkg.targets() {
+            let crate_root = match crate_root(pkg, target) {
+                Ok(crate_root) => crate_root,
+                Err(e) => {
+                    unsupported.push(e);
+                    continue;
+                }
+            };
+            // Only workspace members have their dev-dependencies resolved
+            let dev_only = target.is_test() || target.is_bench() || target.is_example();
+            if dev_only && !is_member {
+                continue;
+            }
+            if !required_features_enabled(target, &features) {
+                continue;
+            }
+            match sources.target_srcs(&crate_root, || {
+                let sources = module_tree::source_files(pkg.root(), &crate_root)?;
+                for path in sources.outside {
+                    // Failing to print a warning is not worth aborting over
+                    let _ = gctx.shell().warn(format!(
+                        "`{}` of `{package_id}` includes `{}` from outside of the package, \
+                         leaving it out of `srcs`",
+                        target.name(),
+                        path.display()
+                    ));
+                }
+                Ok(sources.files)
+            }) {
+                Ok((srcs, crate_root)) => targets.push((target, srcs, crate_root)),
+                Err(reason) => unsupported.push(unsupported_target(pkg, target, reason)),
+            }
+        }
+        // Every target but the library itself and the build script links against the package's
+        // library like cargo does, if we generate it and it isn't only a C ABI library
+        let mut bin_deps = normal_deps.clone();
+        let mut dev_target_deps = test_deps.clone();
+        let has_rust_lib = targets.iter().any(|(target, _, _)| match target.kind() {
+            TargetKind::Lib(crate_types) => LibKind::new(crate_types).is_ok_and(|k| k.rust),
+            _ => false,
+        });
+        if has_rust_lib {
+            let own_lib = format!(":{}", lib_rule_name(package_id));
+            bin_deps.deps.add(own_lib.clone());
+            dev_target_deps.deps.add(own_lib);
+        }
+        // Integration tests and benches can run the package's bins, the ones we generate
+        let bin_exe_env = targets
+            .iter()
+            .filter(|(target, _, _)| target.is_bin())
+            .map(|(bin, _, _)| {
+                (
+                    format!("CARGO_BIN_EXE_{}", bin.name()),
+                    format!("$(location :{})", bin_rule_name(pkg, bin)),
+                )
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        for (target, srcs, crate_root) in targets {
+            let mut cargo_env = pkg_env.clone();
+            cargo_env.insert("CARGO_CRATE_NAME".to_string(), target.crate_name());
+            if target.is_executable() {
+                let bin_name = target.binary_filename();
+                cargo_env.insert(
+                    "CARGO_BIN_NAME".to_string(),
+                    bin_name.unwrap_or_else(|| target.name().to_string()),
+                );
+            }
+            // Every target but the build script itself can read what the build script wrote
+            let mut env = cargo_env.clone();
+            if pkg.has_custom_build() && !target.is_custom_build() {
+                env.insert(
+                    "OUT_DIR".to_string(),
+                    format!(
+                        "$(location :{}[out_dir])",
+                        build_script_run_rule_name(package_id)
+                    ),
+                );
+            }
+
+            match target.kind() {
+                TargetKind::Lib(crate_types) => {
+                    let lib_kind = match LibKind::new(crate_types) {
+                        Ok(lib_kind) => lib_kind,
+                        Err(reason) => {
+                            unsupported.push(unsupported_target(pkg, target, reason));
+                            continue;
+                        }
+                    };
+
+                    let mut lib_labels = labels.clone();
+                    if is_sandboxed_proc_macro(&package_id) {
+                        lib_labels.push("sandboxed_proc_macro".to_string());
+                    }
+                    // proc-macro crates can only be tested with `--extern proc_macro`, which
+                    // `rust_test` has no way to pass
+                    if is_member && target.tested() && !lib_kind.proc_macro {
+                        buck_file.add_rule(
+                            &package_id,
+                            RustTest {
+                                name: format!("{}-unittest", lib_rule_name(package_id)),
+                                visibility: vec!["PUBLIC".to_string()],
+                                srcs: srcs.clone(),
+                                edition: target.edition().to_string(),
+                                crate_root: crate_root.clone(),
+                                crate_name: target.crate_name(),
+                                deps: test_deps.deps.clone(),
+                                named_deps: test_deps.named_deps.clone(),
+                                features: features.clone(),
+                                env: env.clone(),
+                                rustc_flags: rustc_flags.clone(),
+                                env_flags: env_flags.clone(),
+                                labels: dev_labels.clone(),
+                                framework: target.harness(),
+                            },
+                        );
+                    }
+                    buck_file.add_rule(
+                        &package_id,
+                        RustLibrary {
+                            name: lib_rule_name(package_id),
+                            edition: target.edition().to_string(),
+                            visibility: vec!["PUBLIC".to_string()],
+                            srcs,
+                            crate_root,
+                            crate_name: target.crate_name(),
+                            proc_macro: lib_kind.proc_macro,
+                            preferred_linkage: lib_kind.preferred_linkage.clone(),
+                            deps: normal_deps.deps.clone(),
+                            named_deps: normal_deps.named_deps.clone(),
+                            features: features.clone(),
+                            env,
+                            rustc_flags: rustc_flags.clone(),
+                            env_flags: env_flags.clone(),
+                            labels: lib_labels,
+                        },
+                    );
+                    for alias in lib_kind.native_aliases(&lib_rule_name(package_id)) {
+                        buck_file.add_rule(&package_id, alias);
+                    }
+                }
+                TargetKind::Bin => {
+                    if is_member && target.tested() {
+                        buck_file.add_rule(
+                            &package_id,
+                            RustTest {
+                                name: format!("{}-unittest", bin_rule_name(pkg, target)),
+                                visibility: vec!["PUBLIC".to_string()],
+                                srcs: srcs.clone(),
+                                edition: target.edition().to_string(),
+                                crate_root: crate_root.clone(),
+                                crate_name: target.crate_name(),
+                                deps: dev_target_deps.deps.clone(),
+                                named_deps: dev_target_deps.named_deps.clone(),
+                                features: features.clone(),
+                                env: env.clone(),
+                                rustc_flags: rustc_flags.clone(),
+                                env_flags: env_flags.clone(),
+                                labels: dev_labels.clone(),
+                                framework: target.harness(),
+                            },
+                        );
+                    }
+                    buck_file.add_rule(
+                        &package_id,
+                        RustBinary {
+                            name: bin_rule_name(pkg, target),
+                            edition: target.edition().to_string(),
+                            visibility: vec!["PUBLIC".to_string()],
+                            srcs,
+                            deps: bin_deps.deps.clone(),
+                            named_deps: bin_deps.named_deps.clone(),
+                            crate_root,
+                            crate_name: target.crate_name(),
+                            features: features.clone(),
+                            env,
+                            rustc_flags: rustc_flags.clone(),
+                            env_flags: env_flags.clone(),
+                            labels: labels.clone(),
+                        },
+                    );
+                }
+                TargetKind::Test | TargetKind::Bench => {
+                    let mut env = env;
+                    env.extend(bin_exe_env.clone());
+                    let suffix = match target.is_bench() {
+                        true => "bench",
+                        false => "test",
+                    };
+                    // Benches are tests too, `buck2 test` runs them once each like
+                    // `cargo test --benches` does
+                    buck_file.add_rule(
+                        &package_id,
+                        RustTest {
+                            name: format!("{}-{suffix}", target.name()),
+                            visibility: vec!["PUBLIC".to_string()],
+                            srcs,
+                            edition: target.edition().to_string(),
+                            crate_root,
+                            crate_name: target.crate_name(),
+                            deps: dev_target_deps.deps.clone(),
+                            named_deps: dev_target_deps.named_deps.clone(),
+                            features: features.clone(),
+                            env,
+                            rustc_flags: rustc_flags.clone(),
+                            env_flags: env_flags.clone(),
+                            labels: dev_labels.clone(),
+                            framework: target.harness(),
+                        },
+                    );
+                }
+                TargetKind::ExampleBin => {
+                    buck_file.add_rule(
+                        &package_id,
+                        RustBinary {
+                            name: format!("{}-example", target.name()),
+                            edition: target.edition().to_string(),
+                            visibility: vec!["PUBLIC".to_string()],
+                            srcs,
+                            deps: dev_target_deps.deps.clone(),
+                            named_deps: dev_target_deps.named_deps.clone(),
+                            crate_root,
+                            crate_name: target.crate_name(),
+                            features: features.clone(),
+                            env,
+                            rustc_flags: rustc_flags.clone(),
+                            env_flags: env_flags.clone(),
+                            labels: dev_labels.clone(),
+                        },
+                    );
+                }
+                TargetKind::ExampleLib(crate_types) => {
+                    let lib_kind = match LibKind::new(crate_types) {
+                        Ok(lib_kind) => lib_kind,
+                        Err(reason) => {
+                            unsupported.push(unsupported_target(pkg, target, reason));
+                            continue;
+                        }
+                    };
+                    let name = format!("{}-example", target.name());
+                    for alias in lib_kind.native_aliases(&name) {
+                        buck_file.add_rule(&package_id, alias);
+                    }
+                    buck_file.add_rule(
+                        &package_id,
+                        RustLibrary {
+                            name,
+                            edition: target.edition().to_string(),
+                            visibility: vec!["PUBLIC".to_string()],
+                            srcs,
+                            crate_root,
+                            crate_name: target.crate_name(),
+                            proc_macro: lib_kind.proc_macro,
+                            preferred_linkage: lib_kind.preferred_linkage.clone(),
+                            deps: dev_target_deps.deps.clone(),
+                            named_deps: dev_target_deps.named_deps.clone(),
+                            features: features.clone(),
+                            env,
+                            rustc_flags: rustc_flags.clone(),
+                            env_flags: env_flags.clone(),
+                            labels: dev_labels.clone(),
+                        },
+                    );
+                }
+                TargetKind::CustomBuild => {
+                    let build_script_rule = build_script_build_rule_name(package_id);
+                    if let Some(buck_dir) = &buildscript_run_dir {
+                        buck_file.add_load(buildscript_run::load(buck_dir));
+                    }
+                    buck_file.add_rule(
+                        &package_id,
+                        RustBinary {
+                            name: build_script_rule.clone(),
+                            crate_name: "build_script_build".to_string(),
+                            visibility: vec!["PUBLIC".to_string()],
+                            edition: target.edition().to_string(),
+                            srcs,
+                            crate_root,
+                            deps: build_deps.deps.clone(),
+                            named_deps: build_deps.named_deps.clone(),
+                            features: features.clone(),
+                            env: cargo_env.clone(),
+                            rustc_flags: None,
+                            env_flags: None,
+                            labels: Vec::new(),
+                        },
+                    );
+                    buck_file.add_rule(
+                        &package_id,
+                        BuildScriptRun {
+                            buildscript_rule: format!(":{build_script_rule}"),
+                            name: build_script_run_rule_name(package_id),
+                            visibility: vec!["PUBLIC".to_string()],
+                            env: build_script_env.clone(),
+                            links_metadata: links_metadata.clone(),
+                            features: features.clone(),
+                            package_name: pkg.name().to_string(),
+                            version: pkg.version().to_string(),
+                            local_manifest_dir: sources.local_root(),
+                            manifest_dir: sources.fetch_rule(),
+                            manifest_subdir: sources.fetched_subdir(),
+                            inputs: (!metadata.build_script_inputs.is_empty())
+                                .then(|| metadata.build_script_inputs.clone()),
+                            read_env_vars: metadata.read_env_vars_from_build_script.clone(),
+                            env_passthrough: metadata.build_script_env.clone(),
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    if !unsupported.is_empty() {
+        if !opts.keep_going {
+            return Err(Cargo2Buck2Error::UnsupportedTargets(unsupported));
+        }
+        for err in unsupported {
+            // Failing to print a warning is not worth aborting over
+            let _ = gctx.shell().warn(format!("skipping: {err}"));
+        }
+    }
+
+    let vendored = source_backend.into_vendored();
+    // A shared third-party directory is pruned based on its record of workspaces instead
+    let vendor_dir = layout.third_party_dir().join(VENDOR_DIR);
+    let unused_vendored = match opts.vendor && opts.third_party_dir.is_none() {
+        true => vendor::unused(&vendor_dir, &vendored).map_err(|source| Cargo2Buck2Error::Io {
+            path: vendor_dir,
+            source,
+        })?,
+        false => BTreeSet::new(),
+    };
+    Ok(Buckified {
+        buck_files,
+        vendored,
+        files,
+        unused_vendored,
+        root_manifests: BTreeSet::from([ws.root_manifest().to_path_buf()]),
+    })
+}
+
+/// A package as seen when building for one platform
+#[derive(Default)]
+struct PlatformPackage {
+    /// Labels of the package's dependencies, by kind
+    deps: BTreeMap<DepKind, BTreeSet<String>>,
+    /// Labels of dependencies renamed in `Cargo.toml`, by kind and new name
+    named_deps: BTreeMap<DepKind, BTreeMap<String, String>>,
+    /// Normal dependencies, the build script gets the metadata of the ones with `links`
+    normal_dep_ids: BTreeSet<PackageId>,
+    dev_dep_ids: BTreeSet<PackageId>,
+    features: Vec<String>,
+}
+
+/// `deps` and `named_deps` of a target
+#[derive(Clone)]
+struct TargetDeps {
+    deps: PerPlatform<Vec<String>>,
+    named_deps: PerPlatform<BTreeMap<String, String>>,
+}
+
+impl TargetDeps {
+    fn of_kinds(per_platform: &BTreeMap<Platform, PlatformPackage>, kinds: &[DepKind]) -> Self {
+        let mut deps = PerPlatform::default();
+        let mut named_deps = PerPlatform::default();
+        for (platform, platform_pkg) in per_platform {
+            let mut labels = BTreeSet::new();
+            let mut named = BTreeMap::new();
+            for kind in kinds {
+                labels.extend(platform_pkg.deps.get(kind).into_iter().flatten().cloned());
+                named.extend(
+                    platform_pkg
+                        .named_deps
+                        .get(kind)
+                        .cloned()
+                        .unwrap_or_default(),
+                );
+            }
+            deps.0
+                .insert(platform.clone(), labels.into_iter().collect());
+            named_deps.0.insert(platform.clone(), named);
+        }
+        Self { deps, named_deps }
+    }
+}
+
+/// Features of `pkg_id` when building for a platform, `None` when it isn't built for it
+fn activated_features(
+    resolved_features: &[&ResolvedFeatures],
+    pkg_id: PackageId,
+) -> Option<Vec<String>> {
+    let mut features = None::<BTreeSet<String>>;
+    for resolved in resolved_features {
+        for features_for in [FeaturesFor::NormalOrDev, FeaturesFor::HostDep] {
+            if let Some(activated) = resolved.activated_features_unverified(pkg_id, features_for) {
+                features
+                    .get_or_insert_default()
+                    .extend(activated.iter().map(|f| f.to_string()));
+            }
+        }
+    }
+    features.map(|features| features.into_iter().collect())
+}
+
+/// Whether the optional dependency `dep_name` of `pkg_id` is enabled by a feature
+fn is_dep_activated(
+    resolved_features: &[&ResolvedFeatures],
+    pkg_id: PackageId,
+    dep_name: InternedString,
+) -> bool {
+    resolved_features.iter().any(|resolved| {
+        [FeaturesFor::NormalOrDev, FeaturesFor::HostDep]
+            .into_iter()
+            .any(|features_for| resolved.is_dep_activated(pkg_id, features_for, dep_name))
+    })
+}
+
+/// How the crate types a library declares map onto a single `rust_library`.
+///
+/// buck2 builds whichever artifact a dependent needs from the same rule, an rlib for Rust
+/// dependents and a static or shared library for C/C++ ones, so several crate types only
+/// change which of those is preferred.
+struct LibKind {
+    proc_macro: bool,
+    /// Whether Rust crates can depend on it, `false` when only C ABI libraries are built
+    rust: bool,
+    /// `preferred_linkage` of the `rust_library`, `None` leaves it up to dependents
+    preferred_linkage: Option<String>,
+    /// C ABI libraries to build besides the Rust one, as the crate type and the `rust_library`
+    /// sub-target producing it
+    native: Vec<(&'static str, &'static str)>,
+}
+
+impl LibKind {
+    fn new(crate_types: &[CrateType]) -> Result<Self, Unsupported> {
+        let mut rlib = false;
+        let mut dylib = false;
+        let mut proc_macro = false;
+        let mut native = Vec::new();
+        for crate_type in crate_types {
+            match crate_type {
+                CrateType::Lib | CrateType::Rlib => rlib = true,
+                CrateType::Dylib => dylib = true,
+                CrateType::ProcMacro => proc_macro = true,
+                CrateType::Cdylib => native.push(("cdylib", "shared")),
+                CrateType::Staticlib => native.push(("staticlib", "static")),
+                CrateType::Bin | CrateType::Other(_) => {
+                    return Err(Unsupported::CrateType(crate_type.clone()));
+                }
+            }
+        }
+        // rustc links Rust dependents against the rlib when there is one
+        let preferred_linkage = (dylib && !rlib).then(|| "shared".to_string());
+        Ok(Self {
+            proc_macro,
+            rust: rlib || dylib || proc_macro,
+            preferred_linkage,
+            native,
+        })
+    }
+
+    /// `alias`es named after the crate type for the C ABI libraries of the `rust_library`
+    /// called `name`
+    fn native_aliases(&self, name: &str) -> Vec<Alias> {
+        self.native
+            .iter()
+            .map(|(crate_type, sub_target)| Alias {
+                name: format!("{name}-{crate_type}"),
+                actual: format!(":{name}[{sub_target}]"),
+                visibility: vec!["PUBLIC".to_string()],
+            })
+            .collect()
+    }
+}
+
+/// Whether every feature in `target`'s `required-features` is enabled on every platform, cargo
+/// skips the target otherwise.
+///
+/// `dep/feature` requirements are taken as met, checking them needs the features of the
+/// dependency.
+fn required_features_enabled(target: &Target, features: &PerPlatform<Vec<String>>) -> bool {
+    let Some(required) = target.required_features() else {
+        return true;
+    };
+    features.0.values().all(|enabled| {
+        required
+            .iter()
+            .all(|feature| feature.contains('/') || enabled.contains(feature))
+    })
+}
+
+/// The `[package.metadata.cargo2buck2]` section of `pkg`, with the workspace's `overrides`
+/// applied
+fn package_metadata(
+    pkg: &Package,
+    overrides: &BTreeMap<String, CrateOverride>,
+) -> Result<CustomMetadata> {
+    let mut metadata: CustomMetadata = match pkg.manifest().custom_metadata() {
+        Some(custom_meta) => {
+            let cargo2buck2 = custom_meta.get("cargo2buck2");
+            cargo2buck2
+                .map(|v| v.to_owned().try_into::<CustomMetadata>())
+                .transpose()
+                .map_err(|e| Cargo2Buck2Error::InvalidMetadata {
+                    package: pkg.package_id(),
+                    manifest_path: pkg.manifest_path().to_path_buf(),
+                    source: e.into(),
+                })?
+                .unwrap_or_default()
+        }
+        None => CustomMetadata::default(),
+    };
+    if let Some(sandboxed) = overrides
+        .get(pkg.name().as_str())
+        .and_then(|o| o.sandboxed_proc_macro)
+    {
+        metadata.sandboxed_proc_macro = sandboxed;
+    }
+    Ok(metadata)
+}
+
+/// The `CARGO_*` variables cargo sets when compiling any target of `pkg`, with
+/// `CARGO_MANIFEST_DIR` pointing at `manifest_dir`.
+///
+/// `CARGO` and `CARGO_TARGET_TMPDIR` are left out, buck2 runs no cargo and has no target dir.
+fn package_env(pkg: &Package, manifest_dir: &str, is_member: bool) -> BTreeMap<String, String> {
+    let version = pkg.version();
+    let mut env = BTreeMap::from([
+        ("CARGO_MANIFEST_DIR".to_string(), manifest_dir.to_string()),
+        (
+            "CARGO_MANIFEST_PATH".to_string(),
+            format!("{manifest_dir}/Cargo.toml"),
+        ),
+        ("CARGO_PKG_NAME".to_string(), pkg.name().to_string()),
+        ("CARGO_PKG_VERSION".to_string(), version.to_string()),
+        (
+            "CARGO_PKG_VERSION_MAJOR".to_string(),
+            version.major.to_string(),
+        ),
+        (
+            "CARGO_PKG_VERSION_MINOR".to_string(),
+            version.minor.to_string(),
+        ),
+        (
+            "CARGO_PKG_VERSION_PATCH".to_string(),
+            version.patch.to_string(),
+        ),
+        ("CARGO_PKG_VERSION_PRE".to_string(), version.pre.to_string()),
+    ]);
+    // Authors, description, license, rust-version, ... set to an empty string when missing
+    for (key, value) in pkg.manifest().metadata().env_vars() {
+        env.insert(key.to_string(), value.into_owned());
+    }
+    // cargo sets it for the packages it was asked to build, we build the whole workspace
+    if is_member {
+        env.insert("CARGO_PRIMARY_PACKAGE".to_string(), "1".to_string());
+    }
+    env
+}
+
+/// Env vars cargo gives build scripts that depend on the profile
+fn profile_env(profile: &Profile) -> BTreeMap<String, String> {
+    BTreeMap::from([
+        (
+            "PROFILE".to_string(),
+            match profile.root {
+                ProfileRoot::Release => "release",
+                ProfileRoot::Debug => "debug",
+            }
+            .to_string(),
+        ),
+        ("OPT_LEVEL".to_string(), profile.opt_level.to_string()),
+        (
+            "DEBUG".to_string(),
+            (profile.debuginfo.into_inner() != TomlDebugInfo::None).to_string(),
+        ),
+    ])
+}
+
+/// `TARGET` and the `CARGO_CFG_*` env vars cargo gives build scripts when compiling for `kind`.
+///
+/// `HOST`, `RUSTC`, `CARGO_ENCODED_RUSTFLAGS` and `NUM_JOBS` describe the machine running the
+/// build and are left to `buildscript_run`, the ones of the machine buckifying don't belong in
+/// `BUCK` files
+fn target_env(target_data: &RustcTargetData<'_>, kind: CompileKind) -> BTreeMap<String, String> {
+    let mut env = BTreeMap::from([(
+        "TARGET".to_string(),
+        target_data.short_name(&kind).to_string(),
+    )]);
+    let mut cfgs = BTreeMap::<String, Vec<&str>>::new();
+    for cfg in target_data.cfg(kind) {
+        match cfg {
+            Cfg::Name(name) => {
+                cfgs.entry(name.as_str().to_string()).or_default();
+            }
+            Cfg::KeyPair(key, value) => {
+                cfgs.entry(key.as_str().to_string())
+                    .or_default()
+                    .push(value.as_str());
+            }
+        }
+    }
+    for (key, values) in cfgs {
+        // rustc is queried without profile flags so this one is always there, cargo leaves it
+        // out too
+        if key == "debug_assertions" {
+            continue;
+        }
+        env.insert(format!("CARGO_CFG_{}", envify(&key)), values.join(","));
+    }
+    env
+}
+
+/// Add the package specific variables cargo sets when running a build script to `env`
+fn build_script_env(env: &mut BTreeMap<String, String>, features: &[String], links: Option<&str>) {
+    feature_env(env, features);
+    if let Some(links) = links {
+        env.insert("CARGO_MANIFEST_LINKS".to_string(), links.to_string());
+    }
+}
+
+/// Add the `CARGO_FEATURE_*` and `CARGO_CFG_FEATURE` vars of a build script built with
+/// `features` to `env`
+pub(crate) fn feature_env(env: &mut BTreeMap<String, String>, features: &[String]) {
+    for feature in features {
+        env.insert(
+            format!("CARGO_FEATURE_{}", envify(feature)),
+            "1".to_string(),
+        );
+    }
+    env.insert("CARGO_CFG_FEATURE".to_string(), features.join(","));
+}
+
+/// A feature or cfg name as cargo spells it in environment variable names
+fn envify(name: &str) -> String {
+    name.to_uppercase().replace('-', "_")
+}
+
+/// Path of `target`'s entry point relative to the root of `pkg`
+fn crate_root(pkg: &Package, target: &Target) -> Result<String> {
+    let src_path = target
+        .src_path()
+        .path()
+        .ok_or_else(|| unsupported_target(pkg, target, Unsupported::Metabuild))?;
+    let relative = src_path.strip_prefix(pkg.root()).map_err(|_| {
+        unsupported_target(
+            pkg,
+            target,
+            Unsupported::SourceOutsidePackage(src_path.to_path_buf()),
+        )
+    })?;
+    relative
+        .to_str()
+        .map(str::to_string)
+        .ok_or_else(|| Cargo2Buck2Error::NonUtf8Path {
+            package: pkg.package_id(),
+            target: target.name().to_string(),
+            manifest_path: pkg.manifest_path().to_path_buf(),
+            path: src_path.to_path_buf(),
+        })
+}
+
+fn unsupported_target(pkg: &Package, target: &Target, reason: Unsupported) -> Cargo2Buck2Error {
+    Cargo2Buck2Error::Unsupported {
+        package: pkg.package_id(),
+        target: target.name().to_string(),
+        manifest_path: pkg.manifest_path().to_path_buf(),
+        reason,
+    }
+}
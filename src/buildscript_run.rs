@@ -0,0 +1,48 @@
+use std::path::{Path, PathBuf};
+
+use crate::buck_file::{Load, PythonBootstrapBinary};
+
+/// Macro running build scripts
+const BZL_FILE: &str = "cargo_buildscript.bzl";
+/// Script the macro runs build scripts with
+const RUNNER_FILE: &str = "cargo_buildscript_run.py";
+/// Name of the rule wrapping [`RUNNER_FILE`]
+const RUNNER_RULE: &str = "cargo_buildscript_run";
+
+const BZL_TEMPLATE: &str = include_str!("buildscript_run/cargo_buildscript.bzl");
+const RUNNER: &str = include_str!("buildscript_run/cargo_buildscript_run.py");
+
+/// The `load` of our own `buildscript_run` for `BUCK` files using it, `buck_dir` is the
+/// third-party directory relative to the buck2 project root.
+///
+/// The prelude's only exports the `cargo:rustc-cfg` flags of a build script, ours also exports
+/// its `cargo:rustc-env` vars and the metadata `links` packages pass to the build scripts of the
+/// packages depending on them.
+pub fn load(buck_dir: &str) -> Load {
+    Load(
+        format!("//{buck_dir}:{BZL_FILE}"),
+        "buildscript_run".to_string(),
+    )
+}
+
+/// The rule of the third-party `BUCK` file running [`RUNNER_FILE`]
+pub fn runner_rule() -> PythonBootstrapBinary {
+    PythonBootstrapBinary {
+        name: RUNNER_RULE.to_string(),
+        main: RUNNER_FILE.to_string(),
+        visibility: vec!["PUBLIC".to_string()],
+    }
+}
+
+/// The files to write into the third-party directory `dir`, `buck_dir` relative to the buck2
+/// project root
+pub fn files(dir: &Path, buck_dir: &str) -> [(PathBuf, Vec<u8>); 2] {
+    let runner_label = format!("//{buck_dir}:{RUNNER_RULE}");
+    [
+        (
+            dir.join(BZL_FILE),
+            BZL_TEMPLATE.replace("{RUNNER}", &runner_label).into_bytes(),
+        ),
+        (dir.join(RUNNER_FILE), RUNNER.as_bytes().to_vec()),
+    ]
+}
@@ -0,0 +1,184 @@
+use std::path::PathBuf;
+
+use cargo::{CargoResult, GlobalContext};
+use clap::{Args, Parser, Subcommand};
+use serde::{Deserialize, Serialize};
+
+/// Generate buck2 `BUCK` files from `Cargo.toml`/`Cargo.lock`
+#[derive(Debug, Parser)]
+#[command(name = "cargo2buck2", version)]
+pub struct Cli {
+    #[command(flatten)]
+    pub global: GlobalArgs,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Generate `BUCK` files for each of the given cargo workspaces
+    Buckify(BuckifyArgs),
+}
+
+/// Entry point when installed as `cargo-buck2` and invoked as `cargo buck2`
+#[derive(Debug, Parser)]
+#[command(name = "cargo", bin_name = "cargo")]
+pub enum CargoCli {
+    /// Generate buck2 `BUCK` files from `Cargo.toml`/`Cargo.lock`
+    #[command(version)]
+    Buck2(CargoBuck2),
+}
+
+#[derive(Debug, Args)]
+pub struct CargoBuck2 {
+    #[command(flatten)]
+    pub global: GlobalArgs,
+
+    #[command(subcommand)]
+    pub command: Buck2Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Buck2Command {
+    /// Generate `BUCK` files for the workspace enclosing the current directory
+    Generate(GenerateArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct GenerateArgs {
+    /// Path to Cargo.toml
+    #[arg(long, value_name = "PATH")]
+    pub manifest_path: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub common: CommonArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct BuckifyArgs {
+    /// Paths to cargo workspaces (directories containing a `Cargo.toml`)
+    #[arg(required = true)]
+    pub paths: Vec<PathBuf>,
+
+    #[command(flatten)]
+    pub common: CommonArgs,
+}
+
+/// Flags shared by `cargo2buck2 buckify` and `cargo buck2 generate`
+#[derive(Debug, Args)]
+pub struct CommonArgs {
+    /// Directory to write the workspace root's `BUCK` file into, defaults to the workspace root.
+    ///
+    /// `BUCK` files of workspace members are placed relative to it. Only valid when a single
+    /// workspace is given
+    #[arg(long, value_name = "DIR")]
+    pub out_dir: Option<PathBuf>,
+
+    /// Don't write anything, exit with an error if any `BUCK` file on disk is out of date
+    #[arg(long)]
+    pub check: bool,
+
+    #[command(flatten)]
+    pub generation: GenerationArgs,
+
+    #[command(flatten)]
+    pub resolve: ResolveArgs,
+}
+
+/// Flags controlling what rules are generated and where they go
+#[derive(Debug, Args)]
+pub struct GenerationArgs {
+    /// Skip targets that can't be buckified yet instead of failing
+    #[arg(long)]
+    pub keep_going: bool,
+
+    /// Put the rules of all third-party crates in `DIR/BUCK`, shared by every workspace.
+    ///
+    /// By default every workspace gets its own copy in its root `BUCK` file. Workspaces
+    /// buckified into `DIR` by earlier runs are recorded there and buckified again with the
+    /// flags they were given, so no run drops the crates only they need
+    #[arg(long, value_name = "DIR")]
+    pub third_party_dir: Option<PathBuf>,
+
+    /// Copy the sources of third-party crates into `vendor/` next to their `BUCK` file instead
+    /// of having buck2 download them
+    #[arg(long)]
+    pub vendor: bool,
+}
+
+/// Flags controlling how the workspace is resolved, mirroring the ones `cargo build` takes
+#[derive(Debug, Clone, Args, Serialize, Deserialize)]
+pub struct ResolveArgs {
+    /// Space or comma separated list of features to activate
+    #[arg(short = 'F', long, value_delimiter = ',')]
+    pub features: Vec<String>,
+
+    /// Activate all available features
+    #[arg(long)]
+    pub all_features: bool,
+
+    /// Do not activate the `default` feature
+    #[arg(long)]
+    pub no_default_features: bool,
+
+    /// Resolve dependencies for the given target triple, defaults to the host.
+    ///
+    /// Can be given several times, deps and features that differ between triples are wrapped
+    /// in a `select()` on the `config//os` and `config//cpu` constraints
+    #[arg(long = "target", value_name = "TRIPLE")]
+    pub targets: Vec<String>,
+}
+
+/// Flags configuring the cargo `GlobalContext`, mirroring the ones every cargo command takes
+#[derive(Debug, Args)]
+pub struct GlobalArgs {
+    /// Use verbose output (-vv very verbose)
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    pub verbose: u8,
+
+    /// Do not print cargo log messages
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Coloring: auto, always, never
+    #[arg(long, value_name = "WHEN", global = true)]
+    pub color: Option<String>,
+
+    /// Require Cargo.lock and cache are up to date
+    #[arg(long, global = true)]
+    pub frozen: bool,
+
+    /// Require Cargo.lock is up to date
+    #[arg(long, global = true)]
+    pub locked: bool,
+
+    /// Run without accessing the network
+    #[arg(long, global = true)]
+    pub offline: bool,
+
+    /// Override a configuration value
+    #[arg(long = "config", value_name = "KEY=VALUE|PATH", global = true)]
+    pub config: Vec<String>,
+
+    /// Unstable (nightly-only) flags to Cargo
+    #[arg(short = 'Z', value_name = "FLAG", global = true)]
+    pub unstable_flags: Vec<String>,
+}
+
+impl GlobalArgs {
+    /// Apply these flags to `gctx` the same way cargo does for its own commands
+    pub fn configure(&self, gctx: &mut GlobalContext) -> CargoResult<()> {
+        gctx.configure(
+            u32::from(self.verbose),
+            self.quiet,
+            self.color.as_deref(),
+            self.frozen,
+            self.locked,
+            self.offline,
+            &None,
+            &self.unstable_flags,
+            &self.config,
+        )
+    }
+}
@@ -1,5 +1,4 @@
-#![allow(dead_code)]
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 
 use serde::Deserialize;
 
@@ -9,11 +8,66 @@ use serde::Deserialize;
 ///
 /// [package.metadata.cargo2buck2]
 /// read_env_vars_from_build_script = ["MY_VAR"]
+/// build_script_inputs = ["proto/**/*.proto"]
+/// build_script_env = ["PROTOC"]
+/// sandboxed_proc_macro = true
 ///
 #[derive(Debug, Deserialize, Default)]
+#[serde(default)]
 pub struct CustomMetadata {
     /// List of environment variables to read from the output of the build script
     ///
+    /// When non-empty the `cargo:rustc-env=` lines the build script prints are passed on to the
+    /// package's other targets, so they can `env!()` them
+    ///
     /// TODO(pre-alpha): find a better name for this
     pub read_env_vars_from_build_script: BTreeSet<String>,
+
+    /// Glob patterns, relative to the package root, of the files the build script reads. It
+    /// only sees these and `Cargo.toml`
+    pub build_script_inputs: BTreeSet<String>,
+
+    /// Environment variables the build script reads from the environment it is run in, the
+    /// others are not passed through
+    pub build_script_env: BTreeSet<String>,
+
+    /// The proc macro is a pure function of its input tokens. Only surfaces as informational
+    /// labels on the generated rules
+    pub sandboxed_proc_macro: bool,
+}
+
+/// The `cargo2buck2` section in the workspace root's Cargo.toml
+///
+/// example
+///
+/// [workspace.metadata.cargo2buck2.registries.my-registry]
+/// dl = ["https://mirror.example.com/{crate}/{version}/download"]
+///
+/// [workspace.metadata.cargo2buck2.crates.serde_derive]
+/// sandboxed_proc_macro = true
+///
+#[derive(Debug, Deserialize, Default)]
+#[serde(default)]
+pub struct WorkspaceMetadata {
+    /// Per-registry overrides, keyed by the registry's name (`crates-io` for crates.io) or index url
+    pub registries: BTreeMap<String, RegistryOverride>,
+    /// Per-crate overrides of `[package.metadata.cargo2buck2]`, keyed by package name, for
+    /// third-party crates whose manifest we can't edit
+    pub crates: BTreeMap<String, CrateOverride>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RegistryOverride {
+    /// Download url templates used instead of the `dl` field of the registry's `config.json`,
+    /// using the same `{crate}`/`{version}`/`{prefix}`/`{lowerprefix}`/`{sha256-checksum}` markers.
+    ///
+    /// Every template becomes one of the mirror urls of the crate's `http_archive`
+    pub dl: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(default)]
+pub struct CrateOverride {
+    /// Replaces [`CustomMetadata::sandboxed_proc_macro`]
+    pub sandboxed_proc_macro: Option<bool>,
 }
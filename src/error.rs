@@ -0,0 +1,154 @@
+use std::{fmt, path::PathBuf};
+
+use cargo::core::{PackageId, compiler::CrateType};
+
+pub type Result<T, E = Cargo2Buck2Error> = std::result::Result<T, E>;
+
+/// Everything that can go wrong while turning a cargo workspace into `BUCK` files
+#[derive(Debug, thiserror::Error)]
+pub enum Cargo2Buck2Error {
+    #[error("failed to load workspace from `{}`", manifest_path.display())]
+    Workspace {
+        manifest_path: PathBuf,
+        #[source]
+        source: anyhow::Error,
+    },
+
+    #[error("failed to resolve dependencies of workspace `{}`", manifest_path.display())]
+    Resolve {
+        manifest_path: PathBuf,
+        #[source]
+        source: anyhow::Error,
+    },
+
+    #[error("failed to download package `{package}`")]
+    Download {
+        package: PackageId,
+        #[source]
+        source: anyhow::Error,
+    },
+
+    #[error(
+        "invalid `[package.metadata.cargo2buck2]` for package `{package}` in `{}`",
+        manifest_path.display()
+    )]
+    InvalidMetadata {
+        package: PackageId,
+        manifest_path: PathBuf,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    #[error("invalid `[workspace.metadata.cargo2buck2]` in `{}`", manifest_path.display())]
+    InvalidWorkspaceMetadata {
+        manifest_path: PathBuf,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    #[error("failed to read the configuration of registry `{registry}`")]
+    RegistryConfig {
+        registry: String,
+        #[source]
+        source: anyhow::Error,
+    },
+
+    #[error(
+        "path `{}` of target `{target}` in package `{package}` (`{}`) is not valid UTF-8",
+        path.display(),
+        manifest_path.display()
+    )]
+    NonUtf8Path {
+        package: PackageId,
+        target: String,
+        manifest_path: PathBuf,
+        path: PathBuf,
+    },
+
+    #[error(
+        "`{}` (package `{package}`) is outside of the buck2 project root `{}`",
+        path.display(),
+        buck_root.display()
+    )]
+    OutsideBuckRoot {
+        package: PackageId,
+        path: PathBuf,
+        buck_root: PathBuf,
+    },
+
+    #[error(
+        "path package `{package}` at `{}` is outside of the workspace root `{}`, so it has no \
+         place under `--out-dir`, move it inside the workspace or drop `--out-dir`",
+        path.display(),
+        ws_root.display()
+    )]
+    OutsideWorkspace {
+        package: PackageId,
+        path: PathBuf,
+        ws_root: PathBuf,
+    },
+
+    #[error("target `{target}` can't be buckified: {reason}")]
+    UnsupportedPlatform { target: String, reason: String },
+
+    #[error("sources of package `{package}` can't be fetched: {reason}")]
+    UnfetchableSource { package: PackageId, reason: String },
+
+    #[error(
+        "{reason} (target `{target}` in package `{package}`, `{}`)",
+        manifest_path.display()
+    )]
+    Unsupported {
+        package: PackageId,
+        target: String,
+        manifest_path: PathBuf,
+        reason: Unsupported,
+    },
+
+    #[error("{} package(s) or target(s) use unsupported features, pass `--keep-going` to skip them:{}", .0.len(), DisplayList(.0))]
+    UnsupportedTargets(Vec<Cargo2Buck2Error>),
+
+    #[error("failed to write `{}`", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+/// A cargo construct that has no buck2 equivalent yet
+#[derive(Debug)]
+pub enum Unsupported {
+    CrateType(CrateType),
+    /// `metabuild` build scripts have no source path
+    Metabuild,
+    /// The target's source file lives outside of the package root
+    SourceOutsidePackage(PathBuf),
+}
+
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Unsupported::CrateType(crate_type) => {
+                write!(f, "crate type `{crate_type}` is not supported")
+            }
+            Unsupported::Metabuild => write!(f, "`metabuild` is not supported"),
+            Unsupported::SourceOutsidePackage(path) => write!(
+                f,
+                "source file `{}` is outside of the package root",
+                path.display()
+            ),
+        }
+    }
+}
+
+struct DisplayList<'a>(&'a [Cargo2Buck2Error]);
+
+impl fmt::Display for DisplayList<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for err in self.0 {
+            write!(f, "\n  - {err}")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,162 @@
+use std::path::{Component, Path, PathBuf};
+
+use cargo::core::{Package, PackageId, Target};
+
+use crate::error::{Cargo2Buck2Error, Result};
+
+/// Decides which `BUCK` file every package's rules are written to, and how rules in one `BUCK`
+/// file reference rules in another.
+///
+/// Path packages (workspace members and path dependencies) get their own `BUCK` file next to
+/// their `Cargo.toml`, everything else goes into the `BUCK` file at the workspace root, or in
+/// the shared third-party directory if one was given.
+pub struct Layout {
+    ws_root: PathBuf,
+    /// Where the workspace root's `BUCK` file is written, the workspace root unless `--out-dir`
+    /// was given
+    out_root: PathBuf,
+    /// Where the rules of third-party crates are written
+    third_party_dir: PathBuf,
+    /// The buck2 project root, labels are relative to this
+    buck_root: PathBuf,
+}
+
+impl Layout {
+    pub fn new(ws_root: &Path, out_dir: Option<&Path>, third_party_dir: Option<&Path>) -> Self {
+        let out_root = match out_dir {
+            Some(out_dir) => absolute(out_dir),
+            None => ws_root.to_path_buf(),
+        };
+        let third_party_dir = match third_party_dir {
+            Some(third_party_dir) => absolute(third_party_dir),
+            None => out_root.clone(),
+        };
+        let buck_root = find_buck_root(&out_root).unwrap_or_else(|| out_root.clone());
+        Self {
+            ws_root: ws_root.to_path_buf(),
+            out_root,
+            third_party_dir,
+            buck_root,
+        }
+    }
+
+    /// Directory of the `BUCK` file holding the rules of third-party crates
+    pub fn third_party_dir(&self) -> &Path {
+        &self.third_party_dir
+    }
+
+    /// Directory of the `BUCK` file holding the rules of `pkg_id`
+    ///
+    /// Path packages outside of the workspace root get their `BUCK` file next to their
+    /// `Cargo.toml` too, unless `--out-dir` moves the generated tree elsewhere, then they are
+    /// rejected.
+    pub fn package_dir(&self, pkg_id: PackageId) -> Result<PathBuf> {
+        let source_id = pkg_id.source_id();
+        if !source_id.is_path() {
+            return Ok(self.third_party_dir.clone());
+        }
+        let pkg_root = source_id
+            .url()
+            .to_file_path()
+            .expect("path sources always have a file url");
+        match pkg_root.strip_prefix(&self.ws_root) {
+            Ok(relative) => Ok(self.out_root.join(relative)),
+            Err(_) if self.out_root == self.ws_root => Ok(pkg_root),
+            Err(_) => Err(Cargo2Buck2Error::OutsideWorkspace {
+                package: pkg_id,
+                path: pkg_root.clone(),
+                ws_root: self.ws_root.clone(),
+            }),
+        }
+    }
+
+    /// Label of the rule `name` defined in the `BUCK` file of `pkg_id`, as referenced from the
+    /// `BUCK` file in `from`
+    pub fn label(&self, from: &Path, pkg_id: PackageId, name: &str) -> Result<String> {
+        let dir = self.package_dir(pkg_id)?;
+        if dir == from {
+            return Ok(format!(":{name}"));
+        }
+        Ok(format!("//{}:{name}", self.buck_path(pkg_id, &dir)?))
+    }
+
+    /// `dir` relative to the buck2 project root with `/` separators, `pkg_id` is only used for
+    /// the error when it is outside of it
+    pub fn buck_path(&self, pkg_id: PackageId, dir: &Path) -> Result<String> {
+        let relative =
+            dir.strip_prefix(&self.buck_root)
+                .map_err(|_| Cargo2Buck2Error::OutsideBuckRoot {
+                    package: pkg_id,
+                    path: dir.to_path_buf(),
+                    buck_root: self.buck_root.clone(),
+                })?;
+        let components = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>();
+        Ok(components.join("/"))
+    }
+}
+
+/// Name of the `rust_library` rule generated for `pkg_id`
+///
+/// Third-party crates can show up in several versions so their rules carry the version, path
+/// packages are unique within a workspace and are named after the package.
+pub fn lib_rule_name(pkg_id: PackageId) -> String {
+    match pkg_id.source_id().is_path() {
+        true => pkg_id.name().to_string(),
+        false => format!("{}-{}", pkg_id.name(), pkg_id.version()),
+    }
+}
+
+/// Name of the `rust_binary` rule generated for the bin target `bin` of `pkg`
+///
+/// Bins are named after themselves, unless that collides with the package's `rust_library` as
+/// it does for a `src/main.rs` next to a `src/lib.rs`, then they get a `-bin` suffix.
+pub fn bin_rule_name(pkg: &Package, bin: &Target) -> String {
+    let has_lib = pkg.targets().iter().any(Target::is_lib);
+    match has_lib && bin.name() == lib_rule_name(pkg.package_id()) {
+        true => format!("{}-bin", bin.name()),
+        false => bin.name().to_string(),
+    }
+}
+
+/// Name of the `rust_binary` rule building the build script of `pkg_id`
+pub fn build_script_build_rule_name(pkg_id: PackageId) -> String {
+    format!("{}-{}-build-script-build", pkg_id.name(), pkg_id.version())
+}
+
+/// Name of the `buildscript_run` rule running the build script of `pkg_id`
+pub fn build_script_run_rule_name(pkg_id: PackageId) -> String {
+    format!("{}-{}-build-script-run", pkg_id.name(), pkg_id.version())
+}
+
+/// The buck2 project root is marked by a `.buckroot` file, failing that it is the outermost
+/// directory containing a `.buckconfig`
+fn find_buck_root(start: &Path) -> Option<PathBuf> {
+    if let Some(root) = start.ancestors().find(|p| p.join(".buckroot").exists()) {
+        return Some(root.to_path_buf());
+    }
+    start
+        .ancestors()
+        .filter(|p| p.join(".buckconfig").exists())
+        .last()
+        .map(Path::to_path_buf)
+}
+
+/// `path` made absolute with `..` resolved lexically, so it can be compared and stripped as
+/// a prefix
+pub fn absolute(path: &Path) -> PathBuf {
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::CurDir => (),
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
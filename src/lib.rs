@@ -0,0 +1,13 @@
+mod buck_file;
+pub mod buckify;
+mod buildscript_run;
+pub mod cli;
+mod custom_metadata;
+pub mod error;
+mod layout;
+mod module_tree;
+pub mod output;
+mod platform;
+mod shared_dir;
+mod sources;
+mod vendor;
@@ -0,0 +1,407 @@
+use std::{
+    collections::BTreeSet,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::error::Unsupported;
+
+/// The files a target compiles
+pub struct SourceFiles {
+    /// `/` separated paths relative to the package root
+    pub files: BTreeSet<String>,
+    /// Files included from outside of the package, they can't be part of the target's `srcs`
+    pub outside: BTreeSet<PathBuf>,
+}
+
+/// Every file the target rooted at `crate_root` (relative to `pkg_root`) compiles.
+///
+/// Found by following `mod` declarations from the crate root, plus the files named by literal
+/// `include!`, `include_str!` and `include_bytes!` paths. Modules whose file doesn't exist are
+/// skipped, they are usually behind a `cfg` or generated into `OUT_DIR`.
+pub fn source_files(pkg_root: &Path, crate_root: &str) -> Result<SourceFiles, Unsupported> {
+    let mut files = BTreeSet::new();
+    let mut outside = BTreeSet::new();
+    let mut pending = vec![SourceFile {
+        path: pkg_root.join(crate_root),
+        owns_dir: true,
+    }];
+    while let Some(file) = pending.pop() {
+        if !files.insert(relative_to(&file.path, pkg_root)?) {
+            continue;
+        }
+        // Unreadable files are left for rustc to complain about
+        let Ok(src) = std::fs::read_to_string(&file.path) else {
+            continue;
+        };
+        let (modules, includes) = scan(&tokenize(&src), &file);
+        pending.extend(modules);
+        for include in includes.into_iter().filter(|include| include.is_file()) {
+            match relative_to(&include, pkg_root) {
+                Ok(relative) => files.insert(relative),
+                Err(Unsupported::SourceOutsidePackage(path)) => outside.insert(path),
+                Err(e) => return Err(e),
+            };
+        }
+    }
+    Ok(SourceFiles { files, outside })
+}
+
+struct SourceFile {
+    path: PathBuf,
+    /// Crate roots, `mod.rs` files and `#[path]` modules keep their child modules next to
+    /// them, other files in a directory named after themselves
+    owns_dir: bool,
+}
+
+impl SourceFile {
+    fn dir(&self) -> &Path {
+        self.path.parent().unwrap_or(Path::new(""))
+    }
+
+    /// Directory the file's own `mod foo;` declarations are looked up in
+    fn module_dir(&self) -> PathBuf {
+        match self.owns_dir || self.path.file_name() == Some("mod.rs".as_ref()) {
+            true => self.dir().to_path_buf(),
+            false => self.path.with_extension(""),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Punct(char),
+    Str(String),
+}
+
+/// The modules `file` declares and the files it includes
+fn scan(tokens: &[Token], file: &SourceFile) -> (Vec<SourceFile>, Vec<PathBuf>) {
+    let mut modules = Vec::new();
+    let mut includes = Vec::new();
+    // Inline `mod foo { ... }` blocks we're in, with the brace depth they were opened at
+    let mut inline = Vec::<(String, usize)>::new();
+    let mut depth = 0usize;
+    let mut path_attr = None;
+    for (i, token) in tokens.iter().enumerate() {
+        let next = |n: usize| tokens.get(i + n);
+        match token {
+            Token::Punct('{') => depth += 1,
+            Token::Punct('}') => {
+                depth = depth.saturating_sub(1);
+                if inline.last().is_some_and(|(_, d)| *d == depth) {
+                    inline.pop();
+                }
+            }
+            _ => (),
+        }
+        match (token, next(1), next(2), next(3), next(4)) {
+            (
+                Token::Punct('#'),
+                Some(Token::Punct('[')),
+                Some(Token::Ident(attr)),
+                Some(Token::Punct('=')),
+                Some(Token::Str(path)),
+            ) if attr == "path" => path_attr = Some(path.clone()),
+            (Token::Ident(kw), Some(Token::Ident(name)), Some(Token::Punct(';')), _, _)
+                if kw == "mod" =>
+            {
+                let mut dir = file.module_dir();
+                dir.extend(inline.iter().map(|(name, _)| name));
+                let module = match path_attr.take() {
+                    // Outside inline modules `#[path]` is relative to the file itself
+                    Some(path) => Some(SourceFile {
+                        path: match inline.is_empty() {
+                            true => file.dir().join(path),
+                            false => dir.join(path),
+                        },
+                        owns_dir: true,
+                    }),
+                    None => [
+                        dir.join(format!("{name}.rs")),
+                        dir.join(name).join("mod.rs"),
+                    ]
+                    .into_iter()
+                    .find(|p| p.is_file())
+                    .map(|path| SourceFile {
+                        path,
+                        owns_dir: false,
+                    }),
+                };
+                modules.extend(module);
+            }
+            (Token::Ident(kw), Some(Token::Ident(name)), Some(Token::Punct('{')), _, _)
+                if kw == "mod" =>
+            {
+                path_attr = None;
+                inline.push((name.clone(), depth));
+            }
+            (
+                Token::Ident(mac),
+                Some(Token::Punct('!')),
+                Some(Token::Punct('(')),
+                Some(Token::Str(path)),
+                Some(Token::Punct(')')),
+            ) if ["include", "include_str", "include_bytes"].contains(&mac.as_str()) => {
+                includes.push(file.dir().join(path));
+            }
+            (Token::Punct(';' | '{' | '}'), _, _, _, _) => path_attr = None,
+            _ => (),
+        }
+    }
+    (modules, includes)
+}
+
+/// Just enough of a Rust lexer to find `mod` items and include macros: comments are dropped,
+/// string literals are kept and everything else is identifiers or single punctuation
+fn tokenize(src: &str) -> Vec<Token> {
+    let chars = src.chars().collect::<Vec<_>>();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let peek = |n: usize| chars.get(i + n).copied();
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '/' && peek(1) == Some('/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && peek(1) == Some('*') {
+            let mut nesting = 0;
+            while i < chars.len() {
+                if chars[i] == '/' && chars.get(i + 1) == Some(&'*') {
+                    nesting += 1;
+                    i += 2;
+                } else if chars[i] == '*' && chars.get(i + 1) == Some(&'/') {
+                    nesting -= 1;
+                    i += 2;
+                    if nesting == 0 {
+                        break;
+                    }
+                } else {
+                    i += 1;
+                }
+            }
+        } else if let Some((literal, len)) = string_literal(&chars[i..]) {
+            tokens.push(Token::Str(literal));
+            i += len;
+        } else if c == '\'' {
+            // Char literals are skipped, lifetimes are left as punctuation and an identifier
+            if peek(1) == Some('\\') {
+                i += 3;
+                while i < chars.len() && chars[i] != '\'' {
+                    i += 1;
+                }
+                i += 1;
+            } else if peek(2) == Some('\'') {
+                i += 3;
+            } else {
+                tokens.push(Token::Punct('\''));
+                i += 1;
+            }
+        } else if c.is_alphanumeric() || c == '_' {
+            let start = i;
+            // Raw identifiers
+            if c == 'r' && peek(1) == Some('#') {
+                i += 2;
+            }
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let ident = chars[start..i].iter().collect::<String>();
+            tokens.push(Token::Ident(
+                ident.strip_prefix("r#").unwrap_or(&ident).to_string(),
+            ));
+        } else {
+            tokens.push(Token::Punct(c));
+            i += 1;
+        }
+    }
+    tokens
+}
+
+/// The string literal (`"..."`, `r#"..."#`, `b"..."`, ...) at the start of `chars`, and how many
+/// chars it spans
+fn string_literal(chars: &[char]) -> Option<(String, usize)> {
+    let mut i = 0;
+    if matches!(chars.first(), Some('b' | 'c')) {
+        i += 1;
+    }
+    if chars.get(i) == Some(&'r') {
+        i += 1;
+        let hashes = chars[i..].iter().take_while(|c| **c == '#').count();
+        i += hashes;
+        if chars.get(i) != Some(&'"') {
+            return None;
+        }
+        i += 1;
+        let start = i;
+        while i < chars.len() {
+            if chars[i] == '"'
+                && chars[i + 1..]
+                    .iter()
+                    .take(hashes)
+                    .filter(|c| **c == '#')
+                    .count()
+                    == hashes
+            {
+                let literal = chars[start..i].iter().collect();
+                return Some((literal, i + 1 + hashes));
+            }
+            i += 1;
+        }
+        return Some((chars[start..].iter().collect(), chars.len()));
+    }
+    if chars.get(i) != Some(&'"') {
+        return None;
+    }
+    i += 1;
+    let mut literal = String::new();
+    while i < chars.len() && chars[i] != '"' {
+        if chars[i] == '\\' {
+            i += 1;
+            match chars.get(i) {
+                Some('n') => literal.push('\n'),
+                Some('t') => literal.push('\t'),
+                Some(c) => literal.push(*c),
+                None => break,
+            }
+        } else {
+            literal.push(chars[i]);
+        }
+        i += 1;
+    }
+    Some((literal, i + 1))
+}
+
+/// `path` relative to `pkg_root` with `/` separators, `..` components are resolved lexically
+fn relative_to(path: &Path, pkg_root: &Path) -> Result<String, Unsupported> {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::CurDir => (),
+            c => normalized.push(c),
+        }
+    }
+    let relative = normalized
+        .strip_prefix(pkg_root)
+        .map_err(|_| Unsupported::SourceOutsidePackage(normalized.clone()))?;
+    Ok(relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strs(src: &str) -> Vec<String> {
+        tokenize(src)
+            .into_iter()
+            .filter_map(|token| match token {
+                Token::Str(s) => Some(s),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn raw_strings_end_at_matching_hashes() {
+        assert_eq!(
+            strs(r####"r#"a "quoted" b"# br##"c"#d"## r"e\n""####),
+            ["a \"quoted\" b", "c\"#d", "e\\n"]
+        );
+    }
+
+    #[test]
+    fn nested_block_comments_are_skipped() {
+        let tokens = tokenize("/* outer /* inner */ mod hidden; */ mod visible;");
+        assert_eq!(
+            tokens,
+            [
+                Token::Ident("mod".to_string()),
+                Token::Ident("visible".to_string()),
+                Token::Punct(';'),
+            ]
+        );
+    }
+
+    #[test]
+    fn char_literals_are_skipped_and_lifetimes_kept() {
+        let tokens = tokenize(r#"fn f<'a>(x: &'a str) { '"'; '\''; '\u{22}'; }"#);
+        assert!(!tokens.iter().any(|token| matches!(token, Token::Str(_))));
+        let lifetimes = tokens
+            .windows(2)
+            .filter(|pair| matches!(pair, [Token::Punct('\''), Token::Ident(a)] if a == "a"))
+            .count();
+        assert_eq!(lifetimes, 2);
+    }
+
+    /// A fresh package directory holding `files`
+    fn package(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let root = std::env::temp_dir().join(format!(
+            "cargo2buck2-module-tree-{name}-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        for (path, contents) in files {
+            let path = root.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn path_attributes_in_inline_modules_are_relative_to_the_module_dir() {
+        let root = package(
+            "inline-path",
+            &[
+                (
+                    "src/lib.rs",
+                    "mod outer { #[path = \"renamed.rs\"] mod inner; } #[path = \"top.rs\"] mod top; mod sub;",
+                ),
+                ("src/outer/renamed.rs", ""),
+                ("src/top.rs", ""),
+                (
+                    "src/sub.rs",
+                    "mod nested { #[path = \"deep.rs\"] mod deep; }",
+                ),
+                ("src/sub/nested/deep.rs", ""),
+            ],
+        );
+        let sources = source_files(&root, "src/lib.rs").unwrap();
+        let expected = [
+            "src/lib.rs",
+            "src/outer/renamed.rs",
+            "src/sub.rs",
+            "src/sub/nested/deep.rs",
+            "src/top.rs",
+        ];
+        assert_eq!(sources.files, BTreeSet::from(expected.map(String::from)));
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn includes_outside_the_package_are_reported() {
+        let root = package(
+            "outside",
+            &[
+                (
+                    "pkg/src/lib.rs",
+                    "#![doc = include_str!(\"../../README.md\")]",
+                ),
+                ("README.md", ""),
+            ],
+        );
+        let sources = source_files(&root.join("pkg"), "src/lib.rs").unwrap();
+        assert_eq!(sources.files, BTreeSet::from(["src/lib.rs".to_string()]));
+        assert_eq!(sources.outside, BTreeSet::from([root.join("README.md")]));
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
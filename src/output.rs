@@ -0,0 +1,78 @@
+use std::{collections::BTreeMap, io::ErrorKind, path::PathBuf};
+
+use cargo::{CargoResult, GlobalContext};
+use similar::TextDiff;
+
+use crate::{buckify::Buckified, error::Cargo2Buck2Error, vendor};
+
+/// Generated `BUCK` file contents, keyed by the path they belong at
+pub type BuckFiles = BTreeMap<PathBuf, Vec<u8>>;
+
+/// Write the generated files and vendored crates to disk, or when `check` is set verify that
+/// what is on disk matches them
+pub fn write_or_check(buckified: Buckified, check: bool, gctx: &GlobalContext) -> CargoResult<()> {
+    let (files, vendored, unused) = buckified.into_output();
+    if !check {
+        write(&files)?;
+        return vendor::sync(&vendored, &unused, gctx);
+    }
+    let stale_files = self::check(&files, gctx)?;
+    let stale_crates = vendor::check(&vendored, &unused, gctx)?;
+    if stale_files > 0 {
+        anyhow::bail!(
+            "{stale_files} of {} BUCK file(s) are out of date, regenerate them to fix this",
+            files.len()
+        );
+    }
+    if stale_crates > 0 {
+        anyhow::bail!(
+            "{stale_crates} of {} vendored crate(s) are out of date, regenerate them to fix this",
+            vendored.len()
+        );
+    }
+    Ok(())
+}
+
+/// Write every generated file to disk
+fn write(files: &BuckFiles) -> CargoResult<()> {
+    for (path, contents) in files {
+        let io_err = |source| Cargo2Buck2Error::Io {
+            path: path.clone(),
+            source,
+        };
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(io_err)?;
+        }
+        std::fs::write(path, contents).map_err(io_err)?;
+    }
+    Ok(())
+}
+
+/// Compare every generated file against what is on disk, printing a unified diff for each
+/// one that differs.
+///
+/// Returns the number of stale files, nothing is written
+fn check(files: &BuckFiles, gctx: &GlobalContext) -> CargoResult<usize> {
+    let mut stale = 0;
+    for (path, contents) in files {
+        let on_disk = match std::fs::read_to_string(path) {
+            Ok(s) => s,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let generated = String::from_utf8_lossy(contents);
+        if on_disk == generated {
+            continue;
+        }
+        stale += 1;
+
+        let name = path.display().to_string();
+        let diff = TextDiff::from_lines(on_disk.as_str(), generated.as_ref());
+        write!(
+            gctx.shell().out(),
+            "{}",
+            diff.unified_diff().header(&name, &name)
+        )?;
+    }
+    Ok(stale)
+}
@@ -0,0 +1,73 @@
+use cargo::core::compiler::{CompileKind, RustcTargetData};
+use cargo_platform::Cfg;
+
+use crate::error::{Cargo2Buck2Error, Result};
+
+/// A platform generated rules can `select()` on, identified by the buck2 prelude's `os` and
+/// `cpu` constraints
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Platform {
+    /// `config//os:*` constraint
+    pub os: String,
+    /// `config//cpu:*` constraint
+    pub cpu: String,
+}
+
+impl Platform {
+    /// The platform `kind` compiles for
+    pub fn new(target_data: &RustcTargetData<'_>, kind: CompileKind) -> Result<Self> {
+        let triple = target_data.short_name(&kind);
+        let cfg_value = |key: &str| {
+            target_data.cfg(kind).iter().find_map(|cfg| match cfg {
+                Cfg::KeyPair(k, v) if k.name == key => Some(v.as_str()),
+                _ => None,
+            })
+        };
+        let unsupported = |reason: String| Cargo2Buck2Error::UnsupportedPlatform {
+            target: triple.to_string(),
+            reason,
+        };
+
+        let target_os = cfg_value("target_os").unwrap_or_default();
+        let os = os_constraint(target_os)
+            .ok_or_else(|| unsupported(format!("no buck2 constraint for `{target_os}`")))?;
+        let target_arch = cfg_value("target_arch").unwrap_or_default();
+        let cpu = cpu_constraint(target_arch)
+            .ok_or_else(|| unsupported(format!("no buck2 constraint for `{target_arch}`")))?;
+        Ok(Self {
+            os: format!("config//os:{os}"),
+            cpu: format!("config//cpu:{cpu}"),
+        })
+    }
+}
+
+/// Name of the prelude's `config//os` constraint for rustc's `target_os`
+fn os_constraint(target_os: &str) -> Option<&'static str> {
+    Some(match target_os {
+        "android" => "android",
+        "freebsd" => "freebsd",
+        "fuchsia" => "fuchsia",
+        "ios" => "iphoneos",
+        "linux" => "linux",
+        "macos" => "macos",
+        "none" => "none",
+        "wasi" => "wasi",
+        "windows" => "windows",
+        _ => return None,
+    })
+}
+
+/// Name of the prelude's `config//cpu` constraint for rustc's `target_arch`
+fn cpu_constraint(target_arch: &str) -> Option<&'static str> {
+    Some(match target_arch {
+        "aarch64" => "arm64",
+        "arm" => "arm32",
+        "riscv32" => "riscv32",
+        "riscv64" => "riscv64",
+        "wasm32" => "wasm32",
+        "wasm64" => "wasm64",
+        "x86" => "x86_32",
+        "x86_64" => "x86_64",
+        _ => return None,
+    })
+}
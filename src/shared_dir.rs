@@ -0,0 +1,103 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::ErrorKind,
+    path::{Component, Path, PathBuf},
+};
+
+use cargo::CargoResult;
+use serde::{Deserialize, Serialize};
+
+use crate::cli::ResolveArgs;
+
+/// File in a shared third-party directory recording the workspaces whose crates it holds
+pub const RECORD_FILE: &str = "cargo2buck2-workspaces.json";
+
+/// Every workspace using a third-party directory shared through `--third-party-dir`.
+///
+/// A run only resolves the workspaces it was given, the others are looked up here and
+/// buckified again so the shared `BUCK` file and vendored crates keep covering all of them.
+#[derive(Default, Serialize, Deserialize)]
+pub struct SharedDir {
+    /// Keyed by the workspace's root manifest, relative to the shared directory and `/`
+    /// separated so the record can be committed
+    workspaces: BTreeMap<String, RecordedWorkspace>,
+    /// Crates vendored into the shared directory by the last run, relative to it. Only these
+    /// are removed once no workspace uses them anymore
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    vendored: BTreeSet<String>,
+}
+
+/// How a workspace was last buckified, so it can be buckified the same way again
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RecordedWorkspace {
+    #[serde(flatten)]
+    pub resolve: ResolveArgs,
+    pub keep_going: bool,
+}
+
+impl SharedDir {
+    /// Read the record in `dir`, an empty one if the directory isn't shared yet
+    pub fn load(dir: &Path) -> CargoResult<Self> {
+        let path = dir.join(RECORD_FILE);
+        match std::fs::read(&path) {
+            Ok(contents) => serde_json::from_slice(&contents)
+                .map_err(|e| anyhow::format_err!("invalid `{}`: {e}", path.display())),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Record that the workspace of `manifest_path` uses `dir`
+    pub fn insert(&mut self, dir: &Path, manifest_path: &Path, workspace: RecordedWorkspace) {
+        self.workspaces
+            .insert(relative_path(dir, manifest_path), workspace);
+    }
+
+    pub fn remove(&mut self, dir: &Path, manifest_path: &Path) {
+        self.workspaces.remove(&relative_path(dir, manifest_path));
+    }
+
+    /// The root manifest of every recorded workspace, with how it was buckified
+    pub fn workspaces(&self, dir: &Path) -> Vec<(PathBuf, RecordedWorkspace)> {
+        self.workspaces
+            .iter()
+            .map(|(manifest_path, workspace)| (dir.join(manifest_path), workspace.clone()))
+            .collect()
+    }
+
+    /// Record that `vendored` are the crates vendored into `dir` now, returning the previously
+    /// vendored ones that are not anymore
+    pub fn replace_vendored<'a>(
+        &mut self,
+        dir: &Path,
+        vendored: impl IntoIterator<Item = &'a PathBuf>,
+    ) -> BTreeSet<PathBuf> {
+        let vendored = vendored
+            .into_iter()
+            .map(|dest| relative_path(dir, dest))
+            .collect::<BTreeSet<_>>();
+        let previous = std::mem::replace(&mut self.vendored, vendored);
+        previous
+            .difference(&self.vendored)
+            .map(|dest| dir.join(dest))
+            .collect()
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut contents = serde_json::to_vec_pretty(self).expect("the record is valid json");
+        contents.push(b'\n');
+        contents
+    }
+}
+
+/// `path` relative to `base`, both absolute, as a `/` separated string
+fn relative_path(base: &Path, path: &Path) -> String {
+    let base = base.components().collect::<Vec<_>>();
+    let path = path.components().collect::<Vec<_>>();
+    let common = base.iter().zip(&path).take_while(|(a, b)| a == b).count();
+    let ups = base[common..].iter().map(|_| Component::ParentDir);
+    ups.chain(path[common..].iter().copied())
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect::<Vec<_>>()
+        .join("/")
+}
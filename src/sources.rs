@@ -0,0 +1,467 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    path::{Path, PathBuf},
+    task::Poll,
+};
+
+use cargo::{
+    CargoResult, GlobalContext,
+    core::{Package, PackageId, SourceId},
+    sources::{RegistrySource, source::Source as _},
+    util::cache_lock::CacheLockMode,
+};
+use cargo_util::registry::make_dep_path;
+
+use crate::{
+    buck_file::{BuckFile, GitFetch, Glob, HttpArchive, Srcs},
+    custom_metadata::RegistryOverride,
+    error::{Cargo2Buck2Error, Result, Unsupported},
+    vendor::VendoredCrate,
+};
+
+/// The `dl` template of crates.io, hardcoded so buckifying doesn't need to fetch its `config.json`
+const CRATES_IO_DL: &str = "https://static.crates.io/crates/{crate}/{version}/download";
+
+/// Directory next to the third-party `BUCK` file crates are vendored into
+pub const VENDOR_DIR: &str = "vendor";
+
+/// How the sources of third-party crates get into the build
+pub enum SourceBackend<'gctx> {
+    /// buck2 downloads them with `http_archive`/`git_fetch` rules
+    Fetch(RegistryUrls<'gctx>),
+    /// They are copied into `vendor/` next to the `BUCK` file holding their rules
+    Vendor(BTreeMap<PathBuf, VendoredCrate>),
+}
+
+/// Where buck2 gets the sources of a package from
+pub enum PackageSources {
+    /// Path packages, the sources sit next to the package's `BUCK` file
+    Local,
+    /// Sources are downloaded by the rule `rule`, the package lives at `root` inside its output
+    Fetched { rule: String, root: String },
+    /// Sources are vendored into `root`, relative to the package's `BUCK` file
+    Vendored { root: String },
+}
+
+impl SourceBackend<'_> {
+    /// Figure out where the sources of `pkg` come from, adding the rule fetching them to
+    /// `buck_file` (which lives in `pkg_dir`) if needed
+    pub fn package_sources(
+        &mut self,
+        pkg: &Package,
+        buck_file: &mut BuckFile,
+        pkg_dir: &Path,
+    ) -> Result<PackageSources> {
+        let source_id = pkg.package_id().source_id();
+        if source_id.is_path() {
+            return Ok(PackageSources::Local);
+        }
+        match self {
+            SourceBackend::Fetch(registry_urls) => match source_id.is_git() {
+                true => fetch_git(pkg, buck_file),
+                false => fetch_registry(pkg, buck_file, registry_urls),
+            },
+            SourceBackend::Vendor(vendored) => {
+                let root = format!("{VENDOR_DIR}/{}-{}", pkg.name(), pkg.version());
+                let tarball = match source_id.is_git() {
+                    true => None,
+                    false => Some((tarball_path(pkg), checksum(pkg)?.to_string())),
+                };
+                let dest = pkg_dir.join(&root);
+                vendored.insert(
+                    dest.clone(),
+                    VendoredCrate {
+                        src: pkg.root().to_path_buf(),
+                        dest,
+                        tarball,
+                    },
+                );
+                Ok(PackageSources::Vendored { root })
+            }
+        }
+    }
+
+    /// Crates that need to be vendored, empty unless vendoring
+    pub fn into_vendored(self) -> BTreeMap<PathBuf, VendoredCrate> {
+        match self {
+            SourceBackend::Fetch(_) => BTreeMap::new(),
+            SourceBackend::Vendor(vendored) => vendored,
+        }
+    }
+}
+
+impl PackageSources {
+    /// `srcs` and `crate_root` of a target whose entry point is `crate_root` (relative to the
+    /// package root), `files` lists the files it compiles and is only called for path packages
+    pub fn target_srcs(
+        &self,
+        crate_root: &str,
+        files: impl FnOnce() -> Result<BTreeSet<String>, Unsupported>,
+    ) -> Result<(Srcs, String), Unsupported> {
+        Ok(match self {
+            PackageSources::Local => (
+                Srcs::Plain(files()?.into_iter().collect()),
+                crate_root.to_string(),
+            ),
+            PackageSources::Fetched { rule, root } => (
+                Srcs::Plain(vec![format!(":{rule}")]),
+                format!("{root}/{crate_root}"),
+            ),
+            PackageSources::Vendored { root } => (
+                Srcs::Glob(Glob(BTreeSet::from([format!("{root}/**")]))),
+                format!("{root}/{crate_root}"),
+            ),
+        })
+    }
+
+    /// Directory of the package relative to its `BUCK` file, `None` for fetched sources
+    pub fn local_root(&self) -> Option<String> {
+        match self {
+            PackageSources::Local | PackageSources::Fetched { .. } => None,
+            PackageSources::Vendored { root } => Some(root.clone()),
+        }
+    }
+
+    /// Rule downloading the package, `None` unless fetched
+    pub fn fetch_rule(&self) -> Option<String> {
+        match self {
+            PackageSources::Fetched { rule, .. } => Some(format!(":{rule}")),
+            PackageSources::Local | PackageSources::Vendored { .. } => None,
+        }
+    }
+
+    /// Directory of the package inside the output of [`Self::fetch_rule`], `None` when it is
+    /// the whole output
+    pub fn fetched_subdir(&self) -> Option<String> {
+        match self {
+            PackageSources::Fetched { rule, root } => root
+                .strip_prefix(rule.as_str())
+                .and_then(|subdir| subdir.strip_prefix('/'))
+                .map(str::to_string),
+            PackageSources::Local | PackageSources::Vendored { .. } => None,
+        }
+    }
+
+    /// Value of `CARGO_MANIFEST_DIR`, `buck_dir` is the directory of the package's `BUCK` file
+    /// relative to the buck2 project root
+    pub fn manifest_dir(&self, buck_dir: &str) -> String {
+        match self {
+            PackageSources::Local if buck_dir.is_empty() => ".".to_string(),
+            PackageSources::Local => buck_dir.to_string(),
+            // `root` is `rule` or `rule/<subdir>`
+            PackageSources::Fetched { rule, root } => {
+                let subdir = root.strip_prefix(rule.as_str()).unwrap_or_default();
+                format!("$(location :{rule}){subdir}")
+            }
+            PackageSources::Vendored { root } if buck_dir.is_empty() => root.clone(),
+            PackageSources::Vendored { root } => format!("{buck_dir}/{root}"),
+        }
+    }
+}
+
+fn fetch_git(pkg: &Package, buck_file: &mut BuckFile) -> Result<PackageSources> {
+    let package_id = pkg.package_id();
+    let source_id = package_id.source_id();
+    let rev =
+        source_id
+            .precise_git_fragment()
+            .ok_or_else(|| Cargo2Buck2Error::UnfetchableSource {
+                package: package_id,
+                reason: "the git revision is not pinned in Cargo.lock".to_string(),
+            })?;
+    let rule = format!("{}-{}.git", pkg.name(), pkg.version());
+    let root = match git_subdir(pkg.root()) {
+        Some(subdir) => format!("{rule}/{subdir}"),
+        None => rule.clone(),
+    };
+    buck_file.add_rule(
+        &package_id,
+        GitFetch {
+            name: rule.clone(),
+            repo: source_id.url().to_string(),
+            rev: rev.to_string(),
+            visibility: vec!["PUBLIC".to_string()],
+        },
+    );
+    Ok(PackageSources::Fetched { rule, root })
+}
+
+fn fetch_registry(
+    pkg: &Package,
+    buck_file: &mut BuckFile,
+    registry_urls: &mut RegistryUrls<'_>,
+) -> Result<PackageSources> {
+    let package_id = pkg.package_id();
+    let sha256 = checksum(pkg)?;
+    let rule = package_id.tarball_name();
+    buck_file.add_rule(
+        &package_id,
+        HttpArchive {
+            name: rule.clone(),
+            sha256: sha256.to_string(),
+            strip_prefix: rule
+                .strip_suffix(".crate")
+                .expect("tarball names end with `.crate`")
+                .to_string(),
+            urls: registry_urls.urls(package_id, sha256)?,
+            visibility: vec!["PUBLIC".to_string()],
+        },
+    );
+    Ok(PackageSources::Fetched {
+        root: rule.clone(),
+        rule,
+    })
+}
+
+/// The sha256 of a registry crate's tarball, as recorded in Cargo.lock
+fn checksum(pkg: &Package) -> Result<&str> {
+    pkg.summary()
+        .checksum()
+        .ok_or_else(|| Cargo2Buck2Error::UnfetchableSource {
+            package: pkg.package_id(),
+            reason: "Cargo.lock has no checksum for it".to_string(),
+        })
+}
+
+/// The `.crate` tarball cargo unpacked a registry crate from, cargo keeps them in
+/// `registry/cache/<index>/` next to the unpacked `registry/src/<index>/<crate>`
+fn tarball_path(pkg: &Package) -> PathBuf {
+    let mut ancestors = pkg.root().ancestors().skip(1);
+    let index_dir = ancestors.next().and_then(Path::file_name);
+    let registry_dir = ancestors.nth(1);
+    match (registry_dir, index_dir) {
+        (Some(registry_dir), Some(index_dir)) => registry_dir
+            .join("cache")
+            .join(index_dir)
+            .join(pkg.package_id().tarball_name()),
+        _ => pkg.root().with_extension("crate"),
+    }
+}
+
+/// Resolves the download urls of crates, caching the `dl` templates of every registry
+pub struct RegistryUrls<'gctx> {
+    gctx: &'gctx GlobalContext,
+    overrides: BTreeMap<String, RegistryOverride>,
+    templates: HashMap<SourceId, Vec<String>>,
+}
+
+impl<'gctx> RegistryUrls<'gctx> {
+    pub fn new(gctx: &'gctx GlobalContext, overrides: BTreeMap<String, RegistryOverride>) -> Self {
+        Self {
+            gctx,
+            overrides,
+            templates: HashMap::new(),
+        }
+    }
+
+    /// Every url `pkg_id` can be downloaded from, in order of preference
+    fn urls(&mut self, pkg_id: PackageId, checksum: &str) -> Result<Vec<String>> {
+        let source_id = pkg_id.source_id();
+        if !self.templates.contains_key(&source_id) {
+            let templates = self.lookup_templates(source_id)?;
+            self.templates.insert(source_id, templates);
+        }
+        Ok(self.templates[&source_id]
+            .iter()
+            .map(|template| expand_dl_template(template, pkg_id, checksum))
+            .collect())
+    }
+
+    /// Our own overrides win, then the registry's `config.json`
+    fn lookup_templates(&self, source_id: SourceId) -> Result<Vec<String>> {
+        let name = source_id.display_registry_name();
+        let overrides = self
+            .overrides
+            .get(&name)
+            .or_else(|| self.overrides.get(source_id.url().as_str()));
+        if let Some(overrides) = overrides {
+            return Ok(overrides.dl.clone());
+        }
+        if source_id.is_crates_io() {
+            return Ok(vec![CRATES_IO_DL.to_string()]);
+        }
+        let dl =
+            self.registry_dl(source_id)
+                .map_err(|source| Cargo2Buck2Error::RegistryConfig {
+                    registry: name.clone(),
+                    source,
+                })?;
+        match dl {
+            Some(dl) => Ok(vec![dl]),
+            None => Err(Cargo2Buck2Error::RegistryConfig {
+                registry: name,
+                source: anyhow::format_err!(
+                    "the registry has no download url, add one under \
+                     `[workspace.metadata.cargo2buck2.registries]`"
+                ),
+            }),
+        }
+    }
+
+    /// The `dl` field of the registry's `config.json`
+    fn registry_dl(&self, source_id: SourceId) -> CargoResult<Option<String>> {
+        if !source_id.is_remote_registry() {
+            return Ok(None);
+        }
+        let _lock = self
+            .gctx
+            .acquire_package_cache_lock(CacheLockMode::DownloadExclusive)?;
+        let mut source = RegistrySource::remote(source_id, &HashSet::new(), self.gctx)?;
+        loop {
+            match source.config() {
+                Poll::Ready(config) => return Ok(config?.map(|config| config.dl)),
+                Poll::Pending => source.block_until_ready()?,
+            }
+        }
+    }
+}
+
+/// Expand a registry `dl` template the same way cargo does when downloading a crate
+fn expand_dl_template(template: &str, pkg_id: PackageId, checksum: &str) -> String {
+    const MARKERS: [&str; 5] = [
+        "{crate}",
+        "{version}",
+        "{prefix}",
+        "{lowerprefix}",
+        "{sha256-checksum}",
+    ];
+    if !MARKERS.iter().any(|marker| template.contains(marker)) {
+        // Registries predating templates only give the base url
+        return format!("{template}/{}/{}/download", pkg_id.name(), pkg_id.version());
+    }
+    let prefix = make_dep_path(&pkg_id.name(), true);
+    template
+        .replace("{crate}", &pkg_id.name())
+        .replace("{version}", &pkg_id.version().to_string())
+        .replace("{prefix}", &prefix)
+        .replace("{lowerprefix}", &prefix.to_lowercase())
+        .replace("{sha256-checksum}", checksum)
+}
+
+/// Path of a git package relative to the root of the repository it was checked out from, `None`
+/// if the package is at the root
+fn git_subdir(pkg_root: &Path) -> Option<String> {
+    // cargo marks the root of every checkout it makes with a `.cargo-ok` file
+    let checkout_root = pkg_root
+        .ancestors()
+        .find(|p| p.join(".cargo-ok").exists() || p.join(".git").exists())?;
+    let subdir = pkg_root.strip_prefix(checkout_root).ok()?;
+    let components = subdir
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>();
+    match components.is_empty() {
+        true => None,
+        false => Some(components.join("/")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHECKSUM: &str = "0123abcd";
+
+    fn package_id(name: &str) -> PackageId {
+        let source_id = SourceId::from_url("registry+https://example.com/index").unwrap();
+        PackageId::try_new(name, "1.2.3", source_id).unwrap()
+    }
+
+    fn expand(template: &str, name: &str) -> String {
+        expand_dl_template(template, package_id(name), CHECKSUM)
+    }
+
+    #[test]
+    fn markers_are_expanded() {
+        assert_eq!(
+            expand(
+                "https://dl.example.com/{crate}/{version}/{sha256-checksum}.crate",
+                "serde"
+            ),
+            "https://dl.example.com/serde/1.2.3/0123abcd.crate"
+        );
+    }
+
+    #[test]
+    fn prefix_follows_the_index_layout() {
+        let template = "https://dl.example.com/{prefix}/{crate}";
+        assert_eq!(expand(template, "a"), "https://dl.example.com/1/a");
+        assert_eq!(expand(template, "ab"), "https://dl.example.com/2/ab");
+        assert_eq!(expand(template, "abc"), "https://dl.example.com/3/a/abc");
+        assert_eq!(
+            expand(template, "abcd"),
+            "https://dl.example.com/ab/cd/abcd"
+        );
+        assert_eq!(
+            expand(template, "serde_json"),
+            "https://dl.example.com/se/rd/serde_json"
+        );
+    }
+
+    #[test]
+    fn lowerprefix_lowercases_only_the_prefix() {
+        assert_eq!(
+            expand("https://dl.example.com/{prefix}/{crate}", "Inflector"),
+            "https://dl.example.com/In/fl/Inflector"
+        );
+        assert_eq!(
+            expand("https://dl.example.com/{lowerprefix}/{crate}", "Inflector"),
+            "https://dl.example.com/in/fl/Inflector"
+        );
+        assert_eq!(
+            expand("https://dl.example.com/{lowerprefix}/{crate}", "Abc"),
+            "https://dl.example.com/3/a/Abc"
+        );
+    }
+
+    #[test]
+    fn base_url_without_markers_gets_the_api_path() {
+        assert_eq!(
+            expand("https://dl.example.com/api/v1/crates", "serde"),
+            "https://dl.example.com/api/v1/crates/serde/1.2.3/download"
+        );
+    }
+
+    #[test]
+    fn overrides_win_over_the_crates_io_default() {
+        let gctx = GlobalContext::default().unwrap();
+        let crates_io = SourceId::crates_io(&gctx).unwrap();
+        let pkg_id = PackageId::try_new("serde", "1.2.3", crates_io).unwrap();
+
+        let mut urls = RegistryUrls::new(&gctx, BTreeMap::new());
+        assert_eq!(
+            urls.urls(pkg_id, CHECKSUM).unwrap(),
+            ["https://static.crates.io/crates/serde/1.2.3/download"]
+        );
+
+        let mirrors = RegistryOverride {
+            dl: vec![
+                "https://mirror.example.com/{crate}-{version}.crate".to_string(),
+                "https://static.crates.io/crates".to_string(),
+            ],
+        };
+        let mut urls =
+            RegistryUrls::new(&gctx, BTreeMap::from([("crates-io".to_string(), mirrors)]));
+        assert_eq!(
+            urls.urls(pkg_id, CHECKSUM).unwrap(),
+            [
+                "https://mirror.example.com/serde-1.2.3.crate",
+                "https://static.crates.io/crates/serde/1.2.3/download",
+            ]
+        );
+    }
+
+    #[test]
+    fn overrides_can_be_keyed_by_index_url() {
+        let gctx = GlobalContext::default().unwrap();
+        let mirror = RegistryOverride {
+            dl: vec!["https://dl.example.com/{lowerprefix}/{crate}/{version}".to_string()],
+        };
+        let overrides = BTreeMap::from([("https://example.com/index".to_string(), mirror)]);
+        let mut urls = RegistryUrls::new(&gctx, overrides);
+        assert_eq!(
+            urls.urls(package_id("Abcd"), CHECKSUM).unwrap(),
+            ["https://dl.example.com/ab/cd/Abcd/1.2.3"]
+        );
+    }
+}
@@ -0,0 +1,344 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{ErrorKind, Read},
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::Context;
+use cargo::{CargoResult, GlobalContext};
+use cargo_util::Sha256;
+use flate2::read::GzDecoder;
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+/// Files in cargo's unpacked sources that are cargo's own bookkeeping, not part of the crate
+const IGNORED_FILES: [&str; 3] = [".cargo-ok", ".cargo-checksum.json", ".git"];
+
+/// A third-party crate whose sources are copied into the repo instead of being downloaded by buck2
+pub struct VendoredCrate {
+    /// cargo's unpacked copy of the crate, only read for git dependencies
+    pub src: PathBuf,
+    /// Directory the crate is vendored into
+    pub dest: PathBuf,
+    /// The `.crate` tarball the crate is unpacked from and its checksum from Cargo.lock, `None`
+    /// for git dependencies
+    pub tarball: Option<(PathBuf, String)>,
+}
+
+/// Same format as the `.cargo-checksum.json` `cargo vendor` writes, so the vendor directory can
+/// also be used as a cargo directory source
+#[derive(Serialize, Deserialize, PartialEq, Eq)]
+struct CargoChecksum {
+    files: BTreeMap<String, String>,
+    package: Option<String>,
+}
+
+impl VendoredCrate {
+    /// Write the crate's files into `dest`
+    pub fn vendor(&self) -> CargoResult<()> {
+        let files = self.files()?;
+        match std::fs::remove_dir_all(&self.dest) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+            _ => (),
+        }
+        for (relative, contents) in &files {
+            let dest = self.dest.join(relative);
+            if let Some(parent) = dest.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(&dest, contents)?;
+        }
+        let checksum = serde_json::to_string(&self.checksum(&files))?;
+        std::fs::write(self.dest.join(".cargo-checksum.json"), checksum)?;
+        Ok(())
+    }
+
+    /// Whether `dest` holds exactly the files of this crate, and was vendored with the tarball
+    /// Cargo.lock expects
+    pub fn is_up_to_date(&self) -> CargoResult<bool> {
+        let on_disk = match std::fs::read(self.dest.join(".cargo-checksum.json")) {
+            Ok(on_disk) => on_disk,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+        let Ok(on_disk) = serde_json::from_slice::<CargoChecksum>(&on_disk) else {
+            return Ok(false);
+        };
+        let expected = self.checksum(&self.files()?);
+        Ok(on_disk == expected && hash_files(&self.dest)? == expected.files)
+    }
+
+    /// Every file of the crate keyed by its `/` separated path relative to the crate root.
+    ///
+    /// Registry crates are read straight from their tarball after checking it against
+    /// Cargo.lock, so nothing that changed in cargo's unpacked copy ends up vendored
+    fn files(&self) -> CargoResult<BTreeMap<String, Vec<u8>>> {
+        let Some((tarball, expected)) = &self.tarball else {
+            return files(&self.src)?
+                .into_iter()
+                .map(|(relative, path)| Ok((relative, std::fs::read(path)?)))
+                .collect();
+        };
+        let contents = std::fs::read(tarball)
+            .with_context(|| format!("failed to read `{}`", tarball.display()))?;
+        let actual = Sha256::new().update(&contents).finish_hex();
+        if &actual != expected {
+            anyhow::bail!(
+                "checksum of `{}` is {actual}, but Cargo.lock expects {expected}",
+                tarball.display()
+            );
+        }
+        unpack(&contents)
+            .map_err(|e| e.context(format!("failed to unpack `{}`", tarball.display())))
+    }
+
+    fn checksum(&self, files: &BTreeMap<String, Vec<u8>>) -> CargoChecksum {
+        CargoChecksum {
+            files: files
+                .iter()
+                .map(|(relative, contents)| {
+                    (
+                        relative.clone(),
+                        Sha256::new().update(contents).finish_hex(),
+                    )
+                })
+                .collect(),
+            package: self.tarball.as_ref().map(|(_, sum)| sum.clone()),
+        }
+    }
+}
+
+/// The regular files in a `.crate` tarball, without the `<name>-<version>/` directory every
+/// path in it starts with
+fn unpack(tarball: &[u8]) -> CargoResult<BTreeMap<String, Vec<u8>>> {
+    let mut files = BTreeMap::new();
+    let mut archive = tar::Archive::new(GzDecoder::new(tarball));
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path()?.into_owned();
+        let mut components = path.components();
+        components.next();
+        let relative = components
+            .map(|c| match c {
+                Component::Normal(c) => Ok(c.to_string_lossy()),
+                _ => Err(anyhow::format_err!("unexpected path `{}`", path.display())),
+            })
+            .collect::<CargoResult<Vec<_>>>()?
+            .join("/");
+        if relative.is_empty() || IGNORED_FILES.contains(&relative.as_str()) {
+            continue;
+        }
+        let mut contents = Vec::new();
+        entry.read_to_end(&mut contents)?;
+        files.insert(relative, contents);
+    }
+    Ok(files)
+}
+
+/// The sha256 of every file of the crate in `dir`
+fn hash_files(dir: &Path) -> CargoResult<BTreeMap<String, String>> {
+    files(dir)?
+        .into_iter()
+        .map(|(relative, path)| Ok((relative, Sha256::new().update_path(&path)?.finish_hex())))
+        .collect()
+}
+
+/// Every file of the crate in `dir`, keyed by its `/` separated path relative to `dir`
+fn files(dir: &Path) -> CargoResult<BTreeMap<String, PathBuf>> {
+    let mut files = BTreeMap::new();
+    let walker = WalkDir::new(dir).into_iter().filter_entry(|e| {
+        !IGNORED_FILES
+            .iter()
+            .any(|ignored| e.depth() == 1 && e.file_name() == *ignored)
+    });
+    for entry in walker {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative = entry
+            .path()
+            .strip_prefix(dir)?
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        files.insert(relative, entry.path().to_path_buf());
+    }
+    Ok(files)
+}
+
+/// Vendor every crate and remove the `unused` ones
+pub fn sync(
+    vendored: &BTreeMap<PathBuf, VendoredCrate>,
+    unused: &BTreeSet<PathBuf>,
+    gctx: &GlobalContext,
+) -> CargoResult<()> {
+    for path in unused {
+        match std::fs::remove_dir_all(path) {
+            Ok(()) => gctx.shell().status("Removing", path.display())?,
+            Err(e) if e.kind() == ErrorKind::NotFound => (),
+            Err(e) => return Err(e.into()),
+        }
+    }
+    for krate in vendored.values() {
+        if !krate.is_up_to_date()? {
+            gctx.shell().status("Vendoring", krate.dest.display())?;
+            krate.vendor()?;
+        }
+    }
+    Ok(())
+}
+
+/// Number of crates whose vendored copy is missing or out of date, or that are `unused` but
+/// still on disk, nothing is written
+pub fn check(
+    vendored: &BTreeMap<PathBuf, VendoredCrate>,
+    unused: &BTreeSet<PathBuf>,
+    gctx: &GlobalContext,
+) -> CargoResult<usize> {
+    let mut stale = 0;
+    for krate in vendored.values() {
+        if !krate.is_up_to_date()? {
+            stale += 1;
+            gctx.shell().status(
+                "Stale",
+                format!("vendored crate `{}`", krate.dest.display()),
+            )?;
+        }
+    }
+    for path in unused.iter().filter(|path| path.exists()) {
+        stale += 1;
+        gctx.shell().status(
+            "Stale",
+            format!("unused vendored crate `{}`", path.display()),
+        )?;
+    }
+    Ok(stale)
+}
+
+/// Crates in `vendor_dir` we vendored in an earlier run that are no longer in `vendored`.
+///
+/// Only valid when a single workspace vendors into `vendor_dir`, a shared directory records
+/// what each run vendored instead.
+pub fn unused(
+    vendor_dir: &Path,
+    vendored: &BTreeMap<PathBuf, VendoredCrate>,
+) -> std::io::Result<BTreeSet<PathBuf>> {
+    let entries = match std::fs::read_dir(vendor_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(BTreeSet::new()),
+        Err(e) => return Err(e),
+    };
+    let mut unused = BTreeSet::new();
+    for entry in entries {
+        let path = entry?.path();
+        // Only touch directories we vendored ourselves
+        if !vendored.contains_key(&path) && path.join(".cargo-checksum.json").exists() {
+            unused.insert(path);
+        }
+    }
+    Ok(unused)
+}
+
+#[cfg(test)]
+mod tests {
+    use flate2::{Compression, write::GzEncoder};
+
+    use super::*;
+
+    /// A `.crate` tarball of `files` under the `name-1.0.0/` directory
+    fn tarball(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        for (path, contents) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(
+                    &mut header,
+                    format!("name-1.0.0/{path}"),
+                    contents.as_bytes(),
+                )
+                .unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    /// A crate vendored from a tarball of `files` written to a fresh directory
+    fn krate(name: &str, files: &[(&str, &str)]) -> (PathBuf, VendoredCrate) {
+        let root =
+            std::env::temp_dir().join(format!("cargo2buck2-vendor-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let contents = tarball(files);
+        let path = root.join("name-1.0.0.crate");
+        std::fs::write(&path, &contents).unwrap();
+        let sum = Sha256::new().update(&contents).finish_hex();
+        let krate = VendoredCrate {
+            src: root.join("unpacked"),
+            dest: root.join("vendor/name-1.0.0"),
+            tarball: Some((path, sum)),
+        };
+        (root, krate)
+    }
+
+    #[test]
+    fn unpack_strips_the_crate_dir_and_cargo_files() {
+        let files = unpack(&tarball(&[
+            ("Cargo.toml", "[package]"),
+            ("src/lib.rs", "pub fn f() {}"),
+            (".cargo-ok", "ok"),
+        ]))
+        .unwrap();
+        assert_eq!(
+            files,
+            BTreeMap::from([
+                ("Cargo.toml".to_string(), b"[package]".to_vec()),
+                ("src/lib.rs".to_string(), b"pub fn f() {}".to_vec()),
+            ])
+        );
+    }
+
+    #[test]
+    fn checksum_mismatch_is_an_error() {
+        let (root, mut krate) = krate("mismatch", &[("Cargo.toml", "[package]")]);
+        krate.tarball.as_mut().unwrap().1 = "0".repeat(64);
+        let err = krate.vendor().unwrap_err().to_string();
+        assert!(err.contains("but Cargo.lock expects"), "{err}");
+        assert!(!krate.dest.exists());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn missing_tarball_error_names_it() {
+        let (root, krate) = krate("missing", &[("Cargo.toml", "[package]")]);
+        let (path, _) = krate.tarball.as_ref().unwrap();
+        std::fs::remove_file(path).unwrap();
+        let err = krate.vendor().unwrap_err().to_string();
+        assert!(err.contains(&path.display().to_string()), "{err}");
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn vendored_copy_is_up_to_date_until_it_changes() {
+        let (root, krate) = krate(
+            "up-to-date",
+            &[("Cargo.toml", "[package]"), ("src/lib.rs", "")],
+        );
+        assert!(!krate.is_up_to_date().unwrap());
+        krate.vendor().unwrap();
+        assert!(krate.is_up_to_date().unwrap());
+
+        std::fs::write(krate.dest.join("src/lib.rs"), "changed").unwrap();
+        assert!(!krate.is_up_to_date().unwrap());
+        krate.vendor().unwrap();
+        std::fs::write(krate.dest.join("extra.rs"), "").unwrap();
+        assert!(!krate.is_up_to_date().unwrap());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}